@@ -0,0 +1,79 @@
+extern crate leveldb;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use leveldb::util::coding::{decode_varint32_batch, get_varint32, put_varint32};
+
+const COUNT: usize = 1 << 16;
+const ROUNDS: u32 = 200;
+
+fn encode(values: &[u32]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for &v in values {
+        put_varint32(&mut buf, v);
+    }
+    buf
+}
+
+fn per_value(input: &[u8], out: &mut [u32]) {
+    let mut input = input;
+    for value in out.iter_mut() {
+        let (remain, v) = get_varint32(input).unwrap();
+        *value = v;
+        input = remain;
+    }
+}
+
+fn batch(input: &[u8], out: &mut [u32]) {
+    decode_varint32_batch(input, out).unwrap();
+}
+
+fn measure(decode: fn(&[u8], &mut [u32]), input: &[u8], out: &mut [u32]) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        decode(black_box(input), out);
+        black_box(&out);
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn run(name: &str, values: &[u32]) {
+    let input = encode(values);
+    let mut expected = vec![0; values.len()];
+    let mut out = vec![0; values.len()];
+    per_value(&input, &mut expected);
+    batch(&input, &mut out);
+    assert_eq!(expected, values);
+    assert_eq!(out, values);
+
+    let scalar = measure(per_value, &input, &mut out);
+    let batched = measure(batch, &input, &mut out);
+    println!(
+        "{:<12} get_varint32: {:>10?}  decode_varint32_batch: {:>10?}  ({:.2}x)",
+        name,
+        scalar,
+        batched,
+        scalar.as_secs_f64() / batched.as_secs_f64()
+    );
+}
+
+fn main() {
+    let mut seed: u32 = 0x2545_f491;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+
+    let small: Vec<u32> = (0..COUNT).map(|_| next() & 0x7f).collect();
+    let restarts: Vec<u32> = (0..COUNT).map(|i| (i as u32) * 4096).collect();
+    let mixed: Vec<u32> = (0..COUNT).map(|_| next() >> (next() % 32)).collect();
+    let large: Vec<u32> = (0..COUNT).map(|_| next() | 0x1000_0000).collect();
+
+    run("1 byte", &small);
+    run("restarts", &restarts);
+    run("mixed", &mixed);
+    run("5 bytes", &large);
+}
@@ -0,0 +1,482 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use error::{Error, Result};
+use util::coding::{put_fixed64, try_decode_fixed64, EncodedLen, Encoder};
+use util::comparator::Comparator;
+use util::slice::Slice;
+
+pub type SequenceNumber = u64;
+
+/// We leave eight bits empty at the bottom so a type and sequence#
+/// can be packed together into 64-bits.
+pub const MAX_SEQUENCE_NUMBER: SequenceNumber = (1 << 56) - 1;
+
+/// Value types encoded as the last component of internal keys.
+/// DO NOT CHANGE THESE VALUES: they are embedded in the on-disk
+/// data structures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValueType {
+    Deletion = 0x0,
+    Value = 0x1,
+}
+
+/// `VALUE_TYPE_FOR_SEEK` defines the `ValueType` that should be passed when
+/// constructing a `ParsedInternalKey` object for seeking to a particular
+/// sequence number (since we sort sequence numbers in decreasing order
+/// and the value type is embedded as the low 8 bits in the sequence
+/// number in internal keys, we need to use the highest-numbered
+/// `ValueType`, not the lowest).
+pub const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::Value;
+
+impl ValueType {
+    pub fn from_u8(value: u8) -> Option<ValueType> {
+        match value {
+            0x0 => Some(ValueType::Deletion),
+            0x1 => Some(ValueType::Value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedInternalKey<'a> {
+    pub user_key: &'a [u8],
+    pub sequence: SequenceNumber,
+    pub value_type: ValueType,
+}
+
+impl<'a> ParsedInternalKey<'a> {
+    pub fn new(user_key: &'a [u8], sequence: SequenceNumber, value_type: ValueType) -> ParsedInternalKey<'a> {
+        ParsedInternalKey { user_key, sequence, value_type }
+    }
+
+    /// Returns the length of the internal key encoding of `self`.
+    pub fn encoding_length(&self) -> usize {
+        self.user_key.len() + 8
+    }
+}
+
+impl<'a> fmt::Display for ParsedInternalKey<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' @ {} : {}",
+            Slice::new(self.user_key),
+            self.sequence,
+            self.value_type as u8
+        )
+    }
+}
+
+pub fn pack_sequence_and_type(sequence: SequenceNumber, value_type: ValueType) -> u64 {
+    assert!(sequence <= MAX_SEQUENCE_NUMBER);
+    (sequence << 8) | value_type as u64
+}
+
+/// Appends the serialization of `key` to `result`.
+pub fn append_internal_key(result: &mut Vec<u8>, key: &ParsedInternalKey) {
+    result.extend_from_slice(key.user_key);
+    put_fixed64(result, pack_sequence_and_type(key.sequence, key.value_type));
+}
+
+/// Attempts to parse an internal key from `internal_key`, failing with
+/// `Corruption` if the trailer is missing or carries an unknown type.
+pub fn parse_internal_key(internal_key: &[u8]) -> Result<ParsedInternalKey<'_>> {
+    let n = internal_key.len();
+    if n < 8 {
+        return Err(Error::corruption(format!(
+            "internal key of {} bytes is too short",
+            n
+        )));
+    }
+    let num = try_decode_fixed64(&internal_key[n - 8..])?;
+    let value_type = ValueType::from_u8((num & 0xff) as u8).ok_or_else(|| {
+        Error::corruption(format!("unknown value type {} in internal key", num & 0xff))
+    })?;
+    Ok(ParsedInternalKey {
+        user_key: &internal_key[..n - 8],
+        sequence: num >> 8,
+        value_type,
+    })
+}
+
+/// Returns the user key portion of an internal key.
+pub fn extract_user_key(internal_key: &[u8]) -> &[u8] {
+    assert!(internal_key.len() >= 8);
+    &internal_key[..internal_key.len() - 8]
+}
+
+fn extract_trailer(internal_key: &[u8]) -> u64 {
+    assert!(internal_key.len() >= 8);
+    LittleEndian::read_u64(&internal_key[internal_key.len() - 8..])
+}
+
+/// A comparator for internal keys that uses a specified comparator for
+/// the user key portion and breaks ties by decreasing sequence number.
+#[derive(Clone)]
+pub struct InternalKeyComparator {
+    user_comparator: Arc<dyn Comparator>,
+}
+
+impl InternalKeyComparator {
+    pub fn new(user_comparator: Arc<dyn Comparator>) -> InternalKeyComparator {
+        InternalKeyComparator { user_comparator }
+    }
+
+    pub fn user_comparator(&self) -> &Arc<dyn Comparator> {
+        &self.user_comparator
+    }
+
+    pub fn compare_internal_key(&self, a: &InternalKey, b: &InternalKey) -> Ordering {
+        self.compare(a.encode(), b.encode())
+    }
+}
+
+impl Comparator for InternalKeyComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        // Order by:
+        //    increasing user key (according to user-supplied comparator)
+        //    decreasing sequence number
+        //    decreasing type (though sequence# should be enough to disambiguate)
+        self.user_comparator
+            .compare(extract_user_key(a), extract_user_key(b))
+            .then_with(|| extract_trailer(b).cmp(&extract_trailer(a)))
+    }
+
+    fn name(&self) -> &str {
+        "leveldb.InternalKeyComparator"
+    }
+
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &[u8]) {
+        // Attempt to shorten the user portion of the key
+        let user_start = extract_user_key(start);
+        let user_limit = extract_user_key(limit);
+        let mut tmp = user_start.to_vec();
+        self.user_comparator.find_shortest_separator(&mut tmp, user_limit);
+        if tmp.len() < user_start.len()
+            && self.user_comparator.compare(user_start, &tmp) == Ordering::Less
+        {
+            // User key has become shorter physically, but larger logically.
+            // Tack on the earliest possible number to the shortened user key.
+            put_fixed64(&mut tmp, pack_sequence_and_type(MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK));
+            debug_assert_eq!(self.compare(start, &tmp), Ordering::Less);
+            debug_assert_eq!(self.compare(&tmp, limit), Ordering::Less);
+            *start = tmp;
+        }
+    }
+
+    fn find_short_successor(&self, key: &mut Vec<u8>) {
+        let user_key = extract_user_key(key);
+        let mut tmp = user_key.to_vec();
+        self.user_comparator.find_short_successor(&mut tmp);
+        if tmp.len() < user_key.len()
+            && self.user_comparator.compare(user_key, &tmp) == Ordering::Less
+        {
+            // User key has become shorter physically, but larger logically.
+            // Tack on the earliest possible number to the shortened user key.
+            put_fixed64(&mut tmp, pack_sequence_and_type(MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK));
+            debug_assert_eq!(self.compare(key, &tmp), Ordering::Less);
+            *key = tmp;
+        }
+    }
+}
+
+/// Keeps the encoded form of an internal key, so that callers do not
+/// accidentally compare internal keys with plain user keys.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct InternalKey {
+    rep: Vec<u8>,
+}
+
+impl InternalKey {
+    pub fn new(user_key: &[u8], sequence: SequenceNumber, value_type: ValueType) -> InternalKey {
+        let mut rep = Vec::new();
+        append_internal_key(&mut rep, &ParsedInternalKey::new(user_key, sequence, value_type));
+        InternalKey { rep }
+    }
+
+    /// Replaces the contents with `encoded`, failing if it is not a valid
+    /// internal key.
+    pub fn decode_from(&mut self, encoded: &[u8]) -> Result<()> {
+        parse_internal_key(encoded)?;
+        self.rep.clear();
+        self.rep.extend_from_slice(encoded);
+        Ok(())
+    }
+
+    pub fn encode(&self) -> &[u8] {
+        assert!(!self.rep.is_empty());
+        &self.rep
+    }
+
+    pub fn user_key(&self) -> &[u8] {
+        extract_user_key(&self.rep)
+    }
+
+    pub fn set_from(&mut self, key: &ParsedInternalKey) {
+        self.rep.clear();
+        append_internal_key(&mut self.rep, key);
+    }
+
+    pub fn clear(&mut self) {
+        self.rep.clear();
+    }
+}
+
+impl fmt::Debug for InternalKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match parse_internal_key(&self.rep) {
+            Ok(parsed) => write!(f, "{}", parsed),
+            Err(_) => write!(f, "(bad){}", Slice::new(&self.rep)),
+        }
+    }
+}
+
+/// A helper for `MemTable::get()`.
+///
+/// The encoded form is
+/// ```text
+///    klength  varint32               <-- start
+///    userkey  char[klength-8]        <-- kstart
+///    tag      uint64
+///                                    <-- end
+/// ```
+pub struct LookupKey {
+    data: Vec<u8>,
+    kstart: usize,
+}
+
+impl LookupKey {
+    /// Initializes `self` for looking up `user_key` at a snapshot with
+    /// the specified sequence number.
+    pub fn new(user_key: &[u8], sequence: SequenceNumber) -> LookupKey {
+        let klength = (user_key.len() + 8) as u32;
+        let len = EncodedLen::new().varint32(klength).bytes(user_key).fixed64();
+        let mut encoder = Encoder::with_len(len);
+        encoder
+            .varint32(klength)
+            .bytes(user_key)
+            .fixed64(pack_sequence_and_type(sequence, VALUE_TYPE_FOR_SEEK));
+        let data = encoder.into_vec();
+        let kstart = data.len() - user_key.len() - 8;
+        LookupKey { data, kstart }
+    }
+
+    /// Returns a key suitable for lookup in a `MemTable`.
+    pub fn memtable_key(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns an internal key (suitable for passing to an internal iterator)
+    pub fn internal_key(&self) -> &[u8] {
+        &self.data[self.kstart..]
+    }
+
+    /// Returns the user key
+    pub fn user_key(&self) -> &[u8] {
+        &self.data[self.kstart..self.data.len() - 8]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::comparator::BytewiseComparator;
+
+    fn ikey(user_key: &[u8], sequence: SequenceNumber, value_type: ValueType) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        append_internal_key(&mut encoded, &ParsedInternalKey::new(user_key, sequence, value_type));
+        encoded
+    }
+
+    fn icmp() -> InternalKeyComparator {
+        InternalKeyComparator::new(Arc::new(BytewiseComparator))
+    }
+
+    fn shorten(start: &[u8], limit: &[u8]) -> Vec<u8> {
+        let mut start = start.to_vec();
+        icmp().find_shortest_separator(&mut start, limit);
+        start
+    }
+
+    fn short_successor(key: &[u8]) -> Vec<u8> {
+        let mut key = key.to_vec();
+        icmp().find_short_successor(&mut key);
+        key
+    }
+
+    fn test_key(key: &[u8], sequence: SequenceNumber, value_type: ValueType) {
+        let encoded = ikey(key, sequence, value_type);
+        assert_eq!(encoded.len(), key.len() + 8);
+        let decoded = parse_internal_key(&encoded).unwrap();
+        assert_eq!(decoded, ParsedInternalKey::new(key, sequence, value_type));
+        assert_eq!(extract_user_key(&encoded), key);
+    }
+
+    #[test]
+    fn internal_key_encode_decode() {
+        let keys: [&[u8]; 4] = [b"", b"k", b"hello", b"longggggggggggggggggggggg"];
+        let seqs: [SequenceNumber; 12] = [
+            1,
+            2,
+            3,
+            (1 << 8) - 1,
+            1 << 8,
+            (1 << 8) + 1,
+            (1 << 16) - 1,
+            1 << 16,
+            (1 << 16) + 1,
+            (1 << 32) - 1,
+            1 << 32,
+            (1 << 32) + 1,
+        ];
+        for key in keys.iter() {
+            for &seq in seqs.iter() {
+                test_key(key, seq, ValueType::Value);
+                test_key(key, seq, ValueType::Deletion);
+            }
+        }
+        test_key(b"max", MAX_SEQUENCE_NUMBER, ValueType::Value);
+    }
+
+    #[test]
+    fn internal_key_decode_errors() {
+        assert!(parse_internal_key(b"").unwrap_err().is_corruption());
+        assert!(parse_internal_key(b"bar").unwrap_err().is_corruption());
+        assert!(parse_internal_key(b"1234567").unwrap_err().is_corruption());
+
+        // A trailer with an unknown type in its low byte.
+        let mut encoded = b"foo".to_vec();
+        put_fixed64(&mut encoded, (100 << 8) | 0x2);
+        let err = parse_internal_key(&encoded).unwrap_err();
+        assert!(err.is_corruption());
+        assert!(InternalKey::default().decode_from(&encoded).is_err());
+
+        // An exactly 8-byte key has an empty user key.
+        let encoded = ikey(b"", 7, ValueType::Deletion);
+        assert_eq!(
+            parse_internal_key(&encoded).unwrap(),
+            ParsedInternalKey::new(b"", 7, ValueType::Deletion)
+        );
+    }
+
+    #[test]
+    fn internal_key_comparator_order() {
+        let cmp = icmp();
+        // Increasing user key first.
+        assert_eq!(
+            cmp.compare(&ikey(b"a", 1, ValueType::Value), &ikey(b"b", 100, ValueType::Value)),
+            Ordering::Less
+        );
+        // Then decreasing sequence number.
+        assert_eq!(
+            cmp.compare(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 99, ValueType::Value)),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp.compare(&ikey(b"foo", 1, ValueType::Value), &ikey(b"foo", 2, ValueType::Value)),
+            Ordering::Greater
+        );
+        // Then decreasing type.
+        assert_eq!(
+            cmp.compare(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 100, ValueType::Deletion)),
+            Ordering::Less
+        );
+        assert_eq!(
+            cmp.compare(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 100, ValueType::Value)),
+            Ordering::Equal
+        );
+
+        let a = InternalKey::new(b"foo", 5, ValueType::Value);
+        let b = InternalKey::new(b"foo", 4, ValueType::Value);
+        assert_eq!(cmp.compare_internal_key(&a, &b), Ordering::Less);
+    }
+
+    #[test]
+    fn internal_key_short_separator() {
+        // When user keys are same
+        assert_eq!(
+            shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 99, ValueType::Value)),
+            ikey(b"foo", 100, ValueType::Value)
+        );
+        assert_eq!(
+            shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 101, ValueType::Value)),
+            ikey(b"foo", 100, ValueType::Value)
+        );
+        assert_eq!(
+            shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 100, ValueType::Value)),
+            ikey(b"foo", 100, ValueType::Value)
+        );
+        assert_eq!(
+            shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foo", 100, ValueType::Deletion)),
+            ikey(b"foo", 100, ValueType::Value)
+        );
+
+        // When user keys are misordered
+        assert_eq!(
+            shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"bar", 99, ValueType::Value)),
+            ikey(b"foo", 100, ValueType::Value)
+        );
+
+        // When user keys are different, but correctly ordered
+        assert_eq!(
+            shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"hello", 200, ValueType::Value)),
+            ikey(b"g", MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK)
+        );
+
+        // When start user key is prefix of limit user key
+        assert_eq!(
+            shorten(&ikey(b"foo", 100, ValueType::Value), &ikey(b"foobar", 200, ValueType::Value)),
+            ikey(b"foo", 100, ValueType::Value)
+        );
+
+        // When limit user key is prefix of start user key
+        assert_eq!(
+            shorten(&ikey(b"foobar", 100, ValueType::Value), &ikey(b"foo", 200, ValueType::Value)),
+            ikey(b"foobar", 100, ValueType::Value)
+        );
+    }
+
+    #[test]
+    fn internal_key_shortest_successor() {
+        assert_eq!(
+            short_successor(&ikey(b"foo", 100, ValueType::Value)),
+            ikey(b"g", MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK)
+        );
+        assert_eq!(
+            short_successor(&ikey(b"\xff\xff", 100, ValueType::Value)),
+            ikey(b"\xff\xff", 100, ValueType::Value)
+        );
+    }
+
+    #[test]
+    fn lookup_key_layout() {
+        let key = LookupKey::new(b"foo", 5);
+        let tag = pack_sequence_and_type(5, VALUE_TYPE_FOR_SEEK);
+        assert_eq!(tag, 0x501);
+        assert_eq!(
+            key.memtable_key(),
+            &[11, b'f', b'o', b'o', 0x01, 0x05, 0, 0, 0, 0, 0, 0][..]
+        );
+        assert_eq!(key.internal_key(), &key.memtable_key()[1..]);
+        assert_eq!(key.internal_key(), &ikey(b"foo", 5, VALUE_TYPE_FOR_SEEK)[..]);
+        assert_eq!(key.user_key(), b"foo");
+
+        // A user key of 120 bytes or more needs a two-byte length prefix.
+        let user_key = vec![b'x'; 200];
+        let key = LookupKey::new(&user_key, MAX_SEQUENCE_NUMBER);
+        assert_eq!(key.memtable_key().len(), 2 + 200 + 8);
+        assert_eq!(&key.memtable_key()[..2], &[0xd0, 0x01]);
+        assert_eq!(key.internal_key(), &ikey(&user_key, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK)[..]);
+        assert_eq!(key.user_key(), &user_key[..]);
+
+        let key = LookupKey::new(b"", 0);
+        assert_eq!(key.memtable_key(), &[8, 0x01, 0, 0, 0, 0, 0, 0, 0][..]);
+        assert_eq!(key.user_key(), b"");
+    }
+}
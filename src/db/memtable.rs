@@ -0,0 +1,355 @@
+use std::cmp::Ordering;
+use std::sync::Mutex;
+
+use db::dbformat::{
+    extract_user_key, pack_sequence_and_type, InternalKeyComparator, LookupKey, SequenceNumber,
+    ValueType,
+};
+use db::skiplist::{self, KeyComparator, SkipList};
+use util::coding::{
+    get_length_prefixed_slice, put_length_prefixed_slice, try_decode_fixed64, try_encode_fixed64,
+    try_encode_varint32, EncodedLen,
+};
+use util::comparator::Comparator;
+
+// An entry is a pointer to its encoding inside the table's arena:
+//    key_size     : varint32 of internal_key.size()
+//    key bytes    : char[internal_key.size()]
+//    tag          : fixed64, (sequence << 8) | type, the last 8 key bytes
+//    value_size   : varint32 of value.size()
+//    value bytes  : char[value.size()]
+type Entry = *const [u8];
+
+fn entry_internal_key(entry: &[u8]) -> &[u8] {
+    get_length_prefixed_slice(entry)
+        .expect("corrupt memtable entry")
+        .1
+}
+
+#[derive(Clone)]
+struct EntryComparator {
+    comparator: InternalKeyComparator,
+}
+
+impl KeyComparator<Entry> for EntryComparator {
+    fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+        // Internal keys are encoded as length-prefixed strings.
+        let (a, b) = unsafe { (&**a, &**b) };
+        self.comparator
+            .compare(entry_internal_key(a), entry_internal_key(b))
+    }
+}
+
+/// The outcome of looking a key up in a `MemTable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupResult<'a> {
+    /// The memtable holds a value for the key.
+    Found(&'a [u8]),
+    /// The memtable holds a deletion for the key.
+    Deleted,
+    /// The memtable knows nothing about the key.
+    NotPresent,
+}
+
+/// An in-memory, sorted buffer of recent writes, keyed by internal key.
+pub struct MemTable {
+    comparator: EntryComparator,
+    table: SkipList<Entry, EntryComparator>,
+    // serializes writers; readers never take it
+    write_lock: Mutex<()>,
+}
+
+// Entries point into the table's own arena, which lives as long as the
+// memtable and is only written under `write_lock`.
+unsafe impl Send for MemTable {}
+unsafe impl Sync for MemTable {}
+
+impl MemTable {
+    pub fn new(comparator: InternalKeyComparator) -> MemTable {
+        let comparator = EntryComparator { comparator };
+        MemTable {
+            comparator: comparator.clone(),
+            table: SkipList::new(comparator),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Returns an estimate of the number of bytes of data in use by this
+    /// data structure. It is safe to call while the memtable is being
+    /// modified.
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.table.memory_usage()
+    }
+
+    /// Returns an iterator that yields the contents of the memtable.
+    ///
+    /// The keys returned by this iterator are internal keys encoded by
+    /// `append_internal_key` in the db/dbformat module.
+    pub fn iter(&self) -> MemTableIterator<'_> {
+        MemTableIterator {
+            iter: self.table.iter(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Adds an entry into the memtable that maps key to value at the
+    /// specified sequence number and with the specified type.
+    /// Typically value will be empty if value_type == Deletion.
+    pub fn add(&self, sequence: SequenceNumber, value_type: ValueType, key: &[u8], value: &[u8]) {
+        assert!(key.len() + 8 <= u32::MAX as usize, "memtable key too long");
+        assert!(value.len() <= u32::MAX as usize, "memtable value too long");
+        let internal_key_size = (key.len() + 8) as u32;
+        let val_size = value.len() as u32;
+        let encoded_len = EncodedLen::new()
+            .varint32(internal_key_size)
+            .bytes(key)
+            .fixed64()
+            .varint32(val_size)
+            .bytes(value)
+            .encoded_len();
+
+        let _guard = self.write_lock.lock().unwrap();
+        // the write lock makes us the only one touching the arena
+        let buf = unsafe { self.table.arena() }.allocate_bytes(encoded_len);
+        let mut p = try_encode_varint32(buf, internal_key_size).unwrap();
+        buf[p..p + key.len()].copy_from_slice(key);
+        p += key.len();
+        p += try_encode_fixed64(&mut buf[p..], pack_sequence_and_type(sequence, value_type))
+            .unwrap();
+        p += try_encode_varint32(&mut buf[p..], val_size).unwrap();
+        buf[p..p + value.len()].copy_from_slice(value);
+        debug_assert_eq!(p + value.len(), encoded_len);
+        unsafe { self.table.insert(buf as *const [u8]) };
+    }
+
+    /// Looks up the most recent entry for `key` that is visible at the
+    /// lookup key's sequence number.
+    pub fn get(&self, key: &LookupKey) -> LookupResult<'_> {
+        let memkey = key.memtable_key();
+        let mut iter = self.table.iter();
+        iter.seek(&(memkey as *const [u8]));
+        if iter.valid() {
+            // entry format is:
+            //    klength  varint32
+            //    userkey  char[klength]
+            //    tag      uint64
+            //    vlength  varint32
+            //    value    char[vlength]
+            // Check that it belongs to same user key. We do not check the
+            // sequence number since the seek() call above should have skipped
+            // all entries with overly large sequence numbers.
+            let entry = unsafe { &*iter.key() };
+            let (rest, internal_key) =
+                get_length_prefixed_slice(entry).expect("corrupt memtable entry");
+            let user_comparator = self.comparator.comparator.user_comparator();
+            if user_comparator.compare(extract_user_key(internal_key), key.user_key())
+                == Ordering::Equal
+            {
+                // Correct user key
+                let tag = try_decode_fixed64(&internal_key[internal_key.len() - 8..]).unwrap();
+                match ValueType::from_u8((tag & 0xff) as u8) {
+                    Some(ValueType::Value) => {
+                        let value = get_length_prefixed_slice(rest)
+                            .expect("corrupt memtable entry")
+                            .1;
+                        return LookupResult::Found(value);
+                    }
+                    Some(ValueType::Deletion) => return LookupResult::Deleted,
+                    None => {}
+                }
+            }
+        }
+        LookupResult::NotPresent
+    }
+}
+
+/// Iterates over the entries of a `MemTable` in internal key order.
+pub struct MemTableIterator<'a> {
+    iter: skiplist::Iter<'a, Entry, EntryComparator>,
+    // for passing to seek()
+    scratch: Vec<u8>,
+}
+
+impl<'a> MemTableIterator<'a> {
+    pub fn valid(&self) -> bool {
+        self.iter.valid()
+    }
+
+    /// Positions at the first entry whose internal key is at or past `key`.
+    pub fn seek(&mut self, key: &[u8]) {
+        self.scratch.clear();
+        put_length_prefixed_slice(&mut self.scratch, key).expect("internal key too long");
+        self.iter.seek(&(&self.scratch[..] as *const [u8]));
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.iter.seek_to_first();
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.iter.seek_to_last();
+    }
+
+    pub fn next(&mut self) {
+        self.iter.next();
+    }
+
+    pub fn prev(&mut self) {
+        self.iter.prev();
+    }
+
+    /// The internal key at the current position.
+    pub fn key(&self) -> &'a [u8] {
+        entry_internal_key(self.entry())
+    }
+
+    pub fn value(&self) -> &'a [u8] {
+        let (rest, _) = get_length_prefixed_slice(self.entry()).expect("corrupt memtable entry");
+        get_length_prefixed_slice(rest)
+            .expect("corrupt memtable entry")
+            .1
+    }
+
+    fn entry(&self) -> &'a [u8] {
+        unsafe { &*self.iter.key() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use db::dbformat::{parse_internal_key, InternalKey, MAX_SEQUENCE_NUMBER};
+    use util::comparator::BytewiseComparator;
+
+    fn new_memtable() -> MemTable {
+        MemTable::new(InternalKeyComparator::new(Arc::new(BytewiseComparator)))
+    }
+
+    fn get<'a>(mem: &'a MemTable, key: &str, sequence: SequenceNumber) -> LookupResult<'a> {
+        mem.get(&LookupKey::new(key.as_bytes(), sequence))
+    }
+
+    // (user key, sequence, value) at the iterator's position
+    fn entry(iter: &MemTableIterator) -> (String, SequenceNumber, String) {
+        let parsed = parse_internal_key(iter.key()).unwrap();
+        (
+            String::from_utf8(parsed.user_key.to_vec()).unwrap(),
+            parsed.sequence,
+            String::from_utf8(iter.value().to_vec()).unwrap(),
+        )
+    }
+
+    fn sample() -> MemTable {
+        let mem = new_memtable();
+        mem.add(4, ValueType::Value, b"c", b"vc");
+        mem.add(1, ValueType::Value, b"a", b"va1");
+        mem.add(3, ValueType::Deletion, b"b", b"");
+        mem.add(2, ValueType::Value, b"a", b"va2");
+        mem
+    }
+
+    #[test]
+    fn empty() {
+        let mem = new_memtable();
+        assert_eq!(get(&mem, "a", MAX_SEQUENCE_NUMBER), LookupResult::NotPresent);
+        let mut iter = mem.iter();
+        iter.seek_to_first();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn get_results() {
+        let mem = sample();
+        assert_eq!(get(&mem, "a", MAX_SEQUENCE_NUMBER), LookupResult::Found(b"va2"));
+        assert_eq!(get(&mem, "a", 2), LookupResult::Found(b"va2"));
+        assert_eq!(get(&mem, "c", 4), LookupResult::Found(b"vc"));
+        assert_eq!(get(&mem, "b", 3), LookupResult::Deleted);
+        assert_eq!(get(&mem, "b", MAX_SEQUENCE_NUMBER), LookupResult::Deleted);
+    }
+
+    #[test]
+    fn get_respects_sequence() {
+        let mem = sample();
+        // the newer write is hidden from an older snapshot
+        assert_eq!(get(&mem, "a", 1), LookupResult::Found(b"va1"));
+        // nothing for the key existed yet
+        assert_eq!(get(&mem, "a", 0), LookupResult::NotPresent);
+        assert_eq!(get(&mem, "b", 2), LookupResult::NotPresent);
+        assert_eq!(get(&mem, "c", 3), LookupResult::NotPresent);
+    }
+
+    #[test]
+    fn get_checks_user_key() {
+        let mem = sample();
+        // each seek lands on an entry for a different user key
+        assert_eq!(get(&mem, "", MAX_SEQUENCE_NUMBER), LookupResult::NotPresent);
+        assert_eq!(get(&mem, "aa", MAX_SEQUENCE_NUMBER), LookupResult::NotPresent);
+        assert_eq!(get(&mem, "bb", MAX_SEQUENCE_NUMBER), LookupResult::NotPresent);
+        assert_eq!(get(&mem, "d", MAX_SEQUENCE_NUMBER), LookupResult::NotPresent);
+    }
+
+    #[test]
+    fn iterator_order() {
+        let mem = sample();
+        let expected = [
+            ("a".to_string(), 2, "va2".to_string()),
+            ("a".to_string(), 1, "va1".to_string()),
+            ("b".to_string(), 3, "".to_string()),
+            ("c".to_string(), 4, "vc".to_string()),
+        ];
+
+        let mut iter = mem.iter();
+        iter.seek_to_first();
+        for e in &expected {
+            assert!(iter.valid());
+            assert_eq!(&entry(&iter), e);
+            iter.next();
+        }
+        assert!(!iter.valid());
+
+        iter.seek_to_last();
+        for e in expected.iter().rev() {
+            assert!(iter.valid());
+            assert_eq!(&entry(&iter), e);
+            iter.prev();
+        }
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn iterator_seek() {
+        let mem = sample();
+        let mut iter = mem.iter();
+
+        iter.seek(InternalKey::new(b"a", MAX_SEQUENCE_NUMBER, ValueType::Value).encode());
+        assert_eq!(entry(&iter).1, 2);
+        iter.seek(InternalKey::new(b"a", 1, ValueType::Value).encode());
+        assert_eq!(entry(&iter).1, 1);
+        iter.seek(InternalKey::new(b"a", 0, ValueType::Value).encode());
+        assert_eq!(entry(&iter).0, "b");
+        iter.prev();
+        assert_eq!(entry(&iter), ("a".to_string(), 1, "va1".to_string()));
+
+        iter.seek(InternalKey::new(b"bb", MAX_SEQUENCE_NUMBER, ValueType::Value).encode());
+        assert_eq!(entry(&iter).0, "c");
+        iter.seek(InternalKey::new(b"d", MAX_SEQUENCE_NUMBER, ValueType::Value).encode());
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn approximate_memory_usage_grows() {
+        let mem = new_memtable();
+        let mut last = mem.approximate_memory_usage();
+        let value = [b'v'; 1000];
+        for i in 0..1000u64 {
+            mem.add(i + 1, ValueType::Value, format!("key{}", i).as_bytes(), &value);
+            let usage = mem.approximate_memory_usage();
+            assert!(usage >= last);
+            last = usage;
+        }
+        assert!(last > 1000 * 1000);
+        assert_eq!(get(&mem, "key999", MAX_SEQUENCE_NUMBER), LookupResult::Found(&value[..]));
+    }
+}
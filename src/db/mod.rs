@@ -0,0 +1,4 @@
+pub mod skiplist;
+pub mod dbformat;
+pub mod memtable;
+pub mod write_batch;
@@ -0,0 +1,675 @@
+//! Thread safety
+//! -------------
+//!
+//! Writes require external synchronization, most likely a mutex. Reads
+//! require a guarantee that the `SkipList` will not be destroyed while the
+//! read is in progress. Apart from that, reads progress without any internal
+//! locking or synchronization.
+//!
+//! Invariants:
+//!
+//! (1) Allocated nodes are never deleted until the `SkipList` is dropped.
+//! This is trivially guaranteed since nodes live in the list's arena, which
+//! is only freed together with the list.
+//!
+//! (2) The contents of a node except for the next/prev pointers are
+//! immutable after the node has been linked into the `SkipList`. Only
+//! `insert` modifies the list, and it is careful to initialize a node and
+//! use release-stores to publish the nodes in one or more lists.
+
+use std::cell::UnsafeCell;
+use std::cmp::Ordering;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{self, AtomicPtr, AtomicUsize};
+
+use util::arena::Arena;
+use util::random::Random;
+
+const MAX_HEIGHT: usize = 12;
+const BRANCHING: u32 = 4;
+
+/// Orders the keys stored in a `SkipList`.
+pub trait KeyComparator<K> {
+    fn compare(&self, a: &K, b: &K) -> Ordering;
+}
+
+// A node is allocated with room for `height` next pointers; only the first
+// one is declared, the others follow it in the same arena allocation.
+#[repr(C)]
+struct Node<K> {
+    key: K,
+    next: [AtomicPtr<Node<K>>; 1],
+}
+
+impl<K> Node<K> {
+    unsafe fn slot(node: *mut Node<K>, n: usize) -> *const AtomicPtr<Node<K>> {
+        (ptr::addr_of!((*node).next) as *const AtomicPtr<Node<K>>).add(n)
+    }
+
+    // Accessors/mutators for links. Wrapped in methods so we can add the
+    // appropriate barriers as necessary.
+    unsafe fn next(node: *mut Node<K>, n: usize) -> *mut Node<K> {
+        // Use an 'acquire load' so that we observe a fully initialized
+        // version of the returned node.
+        (*Node::slot(node, n)).load(atomic::Ordering::Acquire)
+    }
+
+    unsafe fn set_next(node: *mut Node<K>, n: usize, x: *mut Node<K>) {
+        // Use a 'release store' so that anybody who reads through this
+        // pointer observes a fully initialized version of the inserted node.
+        (*Node::slot(node, n)).store(x, atomic::Ordering::Release);
+    }
+
+    // No-barrier variants that can be safely used in a few locations.
+    unsafe fn no_barrier_next(node: *mut Node<K>, n: usize) -> *mut Node<K> {
+        (*Node::slot(node, n)).load(atomic::Ordering::Relaxed)
+    }
+
+    unsafe fn no_barrier_set_next(node: *mut Node<K>, n: usize, x: *mut Node<K>) {
+        (*Node::slot(node, n)).store(x, atomic::Ordering::Relaxed);
+    }
+}
+
+/// A skiplist whose nodes live in an `Arena`, supporting one writer and any
+/// number of concurrent lock-free readers.
+pub struct SkipList<K, C> {
+    // Immutable after construction
+    compare: C,
+    arena: Arena,
+    head: *mut Node<K>,
+
+    // Modified only by insert(). Read racily by readers, but stale
+    // values are ok.
+    max_height: AtomicUsize,
+
+    // Read/written only by insert().
+    rnd: UnsafeCell<Random>,
+}
+
+unsafe impl<K: Send, C: Send> Send for SkipList<K, C> {}
+unsafe impl<K: Sync, C: Sync> Sync for SkipList<K, C> {}
+
+impl<K: Copy, C: KeyComparator<K>> SkipList<K, C> {
+    /// Creates a new `SkipList` that will use `compare` for comparing keys.
+    pub fn new(compare: C) -> SkipList<K, C> {
+        assert!(mem::align_of::<Node<K>>() <= 8, "skiplist keys must be at most 8-byte aligned");
+        let arena = Arena::new();
+        let head = SkipList::<K, C>::allocate_node(&arena, MAX_HEIGHT);
+        for i in 0..MAX_HEIGHT {
+            unsafe { Node::set_next(head, i, ptr::null_mut()) };
+        }
+        SkipList {
+            compare,
+            arena,
+            head,
+            max_height: AtomicUsize::new(1),
+            rnd: UnsafeCell::new(Random::new(0xdead_beef)),
+        }
+    }
+
+    /// Inserts `key` into the list.
+    ///
+    /// REQUIRES: nothing that compares equal to key is currently in the list.
+    ///
+    /// # Safety
+    ///
+    /// Calls to `insert` and to allocations from `arena()` must not overlap.
+    /// Readers may run concurrently with them.
+    pub unsafe fn insert(&self, key: K) {
+        let mut prev = [ptr::null_mut(); MAX_HEIGHT];
+        let x = self.find_greater_or_equal(&key, Some(&mut prev));
+
+        // Our data structure does not allow duplicate insertion
+        assert!(x.is_null() || !self.equal(&key, &(*x).key));
+
+        let height = self.random_height();
+        if height > self.get_max_height() {
+            for p in prev.iter_mut().take(height).skip(self.get_max_height()) {
+                *p = self.head;
+            }
+            // It is ok to mutate max_height without any synchronization
+            // with concurrent readers. A concurrent reader that observes
+            // the new value of max_height will see either the old value of
+            // new level pointers from head (null), or a new value set in
+            // the loop below. In the former case the reader will
+            // immediately drop to the next level since null sorts after all
+            // keys. In the latter case the reader will use the new node.
+            self.max_height.store(height, atomic::Ordering::Relaxed);
+        }
+
+        let x = self.new_node(key, height);
+        for (i, &p) in prev.iter().enumerate().take(height) {
+            // no_barrier_set_next() suffices since we will add a barrier when
+            // we publish a pointer to "x" in prev[i].
+            Node::no_barrier_set_next(x, i, Node::no_barrier_next(p, i));
+            Node::set_next(p, i, x);
+        }
+    }
+
+    /// Returns true iff an entry that compares equal to `key` is in the list.
+    pub fn contains(&self, key: &K) -> bool {
+        let x = self.find_greater_or_equal(key, None);
+        !x.is_null() && self.equal(key, unsafe { &(*x).key })
+    }
+
+    /// Returns an iterator over the list. The returned iterator is not valid
+    /// until it is positioned.
+    pub fn iter(&self) -> Iter<'_, K, C> {
+        Iter { list: self, node: ptr::null_mut() }
+    }
+
+    /// The arena that backs the list's nodes.
+    ///
+    /// # Safety
+    ///
+    /// Allocating from the arena must be synchronized with `insert` the same
+    /// way concurrent inserts are.
+    pub unsafe fn arena(&self) -> &Arena {
+        &self.arena
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.arena.memory_usage()
+    }
+
+    fn get_max_height(&self) -> usize {
+        self.max_height.load(atomic::Ordering::Relaxed)
+    }
+
+    fn allocate_node(arena: &Arena, height: usize) -> *mut Node<K> {
+        let size = mem::size_of::<Node<K>>() + mem::size_of::<AtomicPtr<Node<K>>>() * (height - 1);
+        arena.allocate_aligned(size) as *mut Node<K>
+    }
+
+    unsafe fn new_node(&self, key: K, height: usize) -> *mut Node<K> {
+        let node = SkipList::<K, C>::allocate_node(&self.arena, height);
+        ptr::write(ptr::addr_of_mut!((*node).key), key);
+        node
+    }
+
+    unsafe fn random_height(&self) -> usize {
+        // Increase height with probability 1 in BRANCHING
+        let rnd = &mut *self.rnd.get();
+        let mut height = 1;
+        while height < MAX_HEIGHT && rnd.one_in(BRANCHING) {
+            height += 1;
+        }
+        height
+    }
+
+    fn equal(&self, a: &K, b: &K) -> bool {
+        self.compare.compare(a, b) == Ordering::Equal
+    }
+
+    // Returns true if key is greater than the data stored in "n"
+    fn key_is_after_node(&self, key: &K, n: *mut Node<K>) -> bool {
+        // null n is considered infinite
+        !n.is_null() && self.compare.compare(unsafe { &(*n).key }, key) == Ordering::Less
+    }
+
+    // Returns the earliest node that comes at or after key.
+    // Returns null if there is no such node.
+    //
+    // If prev is non-null, fills prev[level] with pointer to previous
+    // node at "level" for every level in [0..max_height-1].
+    fn find_greater_or_equal(
+        &self,
+        key: &K,
+        mut prev: Option<&mut [*mut Node<K>; MAX_HEIGHT]>,
+    ) -> *mut Node<K> {
+        let mut x = self.head;
+        let mut level = self.get_max_height() - 1;
+        loop {
+            let next = unsafe { Node::next(x, level) };
+            if self.key_is_after_node(key, next) {
+                // Keep searching in this list
+                x = next;
+            } else {
+                if let Some(ref mut prev) = prev {
+                    prev[level] = x;
+                }
+                if level == 0 {
+                    return next;
+                }
+                // Switch to next list
+                level -= 1;
+            }
+        }
+    }
+
+    // Returns the latest node with a key < key.
+    // Returns head if there is no such node.
+    fn find_less_than(&self, key: &K) -> *mut Node<K> {
+        let mut x = self.head;
+        let mut level = self.get_max_height() - 1;
+        loop {
+            debug_assert!(
+                x == self.head
+                    || self.compare.compare(unsafe { &(*x).key }, key) == Ordering::Less
+            );
+            let next = unsafe { Node::next(x, level) };
+            if next.is_null()
+                || self.compare.compare(unsafe { &(*next).key }, key) != Ordering::Less
+            {
+                if level == 0 {
+                    return x;
+                }
+                // Switch to next list
+                level -= 1;
+            } else {
+                x = next;
+            }
+        }
+    }
+
+    // Returns the last node in the list.
+    // Returns head if list is empty.
+    fn find_last(&self) -> *mut Node<K> {
+        let mut x = self.head;
+        let mut level = self.get_max_height() - 1;
+        loop {
+            let next = unsafe { Node::next(x, level) };
+            if next.is_null() {
+                if level == 0 {
+                    return x;
+                }
+                // Switch to next list
+                level -= 1;
+            } else {
+                x = next;
+            }
+        }
+    }
+}
+
+/// Iteration over the contents of a skip list.
+pub struct Iter<'a, K: 'a, C: 'a> {
+    list: &'a SkipList<K, C>,
+    node: *mut Node<K>,
+}
+
+impl<'a, K: Copy, C: KeyComparator<K>> Iter<'a, K, C> {
+    /// Returns true iff the iterator is positioned at a valid node.
+    pub fn valid(&self) -> bool {
+        !self.node.is_null()
+    }
+
+    /// Returns the key at the current position.
+    ///
+    /// REQUIRES: valid()
+    pub fn key(&self) -> K {
+        assert!(self.valid());
+        unsafe { (*self.node).key }
+    }
+
+    /// Advances to the next position.
+    ///
+    /// REQUIRES: valid()
+    pub fn next(&mut self) {
+        assert!(self.valid());
+        self.node = unsafe { Node::next(self.node, 0) };
+    }
+
+    /// Advances to the previous position.
+    ///
+    /// REQUIRES: valid()
+    pub fn prev(&mut self) {
+        // Instead of using explicit "prev" links, we just search for the
+        // last node that falls before key.
+        assert!(self.valid());
+        self.node = self.list.find_less_than(unsafe { &(*self.node).key });
+        if self.node == self.list.head {
+            self.node = ptr::null_mut();
+        }
+    }
+
+    /// Advance to the first entry with a key >= target
+    pub fn seek(&mut self, target: &K) {
+        self.node = self.list.find_greater_or_equal(target, None);
+    }
+
+    /// Position at the first entry in list.
+    /// Final state of iterator is valid() iff list is not empty.
+    pub fn seek_to_first(&mut self) {
+        self.node = unsafe { Node::next(self.list.head, 0) };
+    }
+
+    /// Position at the last entry in list.
+    /// Final state of iterator is valid() iff list is not empty.
+    pub fn seek_to_last(&mut self) {
+        self.node = self.list.find_last();
+        if self.node == self.list.head {
+            self.node = ptr::null_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::sync::atomic::{AtomicBool, AtomicU64};
+    use std::thread;
+
+    use super::*;
+    use util::hash::hash;
+
+    struct OrdComparator;
+
+    impl<K: Ord> KeyComparator<K> for OrdComparator {
+        fn compare(&self, a: &K, b: &K) -> Ordering {
+            a.cmp(b)
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let list = SkipList::<u64, _>::new(OrdComparator);
+        assert!(!list.contains(&10));
+
+        let mut iter = list.iter();
+        assert!(!iter.valid());
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek(&100);
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn insert_and_lookup() {
+        const N: u32 = 2000;
+        const R: u64 = 5000;
+        let mut rnd = Random::new(1000);
+        let mut keys = BTreeSet::new();
+        let list = SkipList::new(OrdComparator);
+        for _ in 0..N {
+            let key = u64::from(rnd.next_u32()) % R;
+            if keys.insert(key) {
+                unsafe { list.insert(key) };
+            }
+        }
+
+        for i in 0..R {
+            assert_eq!(list.contains(&i), keys.contains(&i), "{}", i);
+        }
+
+        // Simple iterator tests
+        {
+            let mut iter = list.iter();
+            assert!(!iter.valid());
+
+            iter.seek(&0);
+            assert!(iter.valid());
+            assert_eq!(*keys.iter().next().unwrap(), iter.key());
+
+            iter.seek_to_first();
+            assert!(iter.valid());
+            assert_eq!(*keys.iter().next().unwrap(), iter.key());
+
+            iter.seek_to_last();
+            assert!(iter.valid());
+            assert_eq!(*keys.iter().next_back().unwrap(), iter.key());
+        }
+
+        // Forward iteration test
+        for i in 0..R {
+            let mut iter = list.iter();
+            iter.seek(&i);
+
+            // Compare against model iterator
+            let mut model = keys.range(i..);
+            for _ in 0..3 {
+                match model.next() {
+                    Some(&k) => {
+                        assert!(iter.valid());
+                        assert_eq!(k, iter.key());
+                        iter.next();
+                    }
+                    None => {
+                        assert!(!iter.valid());
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Backward iteration test
+        {
+            let mut iter = list.iter();
+            iter.seek_to_last();
+
+            // Compare against model iterator
+            for &k in keys.iter().rev() {
+                assert!(iter.valid());
+                assert_eq!(k, iter.key());
+                iter.prev();
+            }
+            assert!(!iter.valid());
+        }
+    }
+
+    #[test]
+    fn wide_keys() {
+        // Keys wider than a pointer move the next slots further into the
+        // node; check they still line up for every height.
+        let list = SkipList::new(OrdComparator);
+        for i in (0..1000u64).rev() {
+            unsafe { list.insert((i, !i, i.wrapping_mul(0x9e37_79b9_7f4a_7c15))) };
+        }
+        let mut iter = list.iter();
+        iter.seek_to_first();
+        for i in 0..1000u64 {
+            assert!(iter.valid());
+            assert_eq!(iter.key(), (i, !i, i.wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+            iter.next();
+        }
+        assert!(!iter.valid());
+        iter.seek(&(500, 0, 0));
+        assert_eq!(iter.key().0, 500);
+        iter.prev();
+        assert_eq!(iter.key().0, 499);
+    }
+
+    // We want to make sure that with a single writer and multiple
+    // concurrent readers (with no synchronization other than when a
+    // reader's iterator is created), the reader always observes all the
+    // data that was present in the skip list when the iterator was
+    // constructed. Because insertions are happening concurrently, we may
+    // also observe new values that were inserted since the iterator was
+    // constructed, but we should never miss any values that were present
+    // at iterator construction time.
+    //
+    // We generate multi-part keys:
+    //     <key,gen,hash>
+    // where:
+    //     key is in range [0..K-1]
+    //     gen is a generation number for key
+    //     hash is hash(key,gen)
+    //
+    // The insertion code picks a random key, sets gen to be 1 + the last
+    // generation number inserted for that key, and sets hash to
+    // hash(key,gen).
+    //
+    // At the beginning of a read, we snapshot the last inserted generation
+    // number for each key. We then iterate, including random calls to
+    // next() and seek(). For every key we encounter, we check that it is
+    // either expected given the initial snapshot or has been concurrently
+    // added since the iterator started.
+    const K: u64 = 4;
+
+    fn key(key: u64) -> u64 {
+        key >> 40
+    }
+
+    fn gen(key: u64) -> u64 {
+        (key >> 8) & 0xffff_ffff
+    }
+
+    fn hash_bits(key: u64) -> u64 {
+        key & 0xff
+    }
+
+    fn hash_numbers(k: u64, g: u64) -> u64 {
+        let mut data = [0u8; 16];
+        data[..8].copy_from_slice(&k.to_le_bytes());
+        data[8..].copy_from_slice(&g.to_le_bytes());
+        u64::from(hash(&data, 0))
+    }
+
+    fn make_key(k: u64, g: u64) -> u64 {
+        assert!(k <= K); // We sometimes pass K to seek to the end of the skiplist
+        assert!(g <= 0xffff_ffff);
+        (k << 40) | (g << 8) | (hash_numbers(k, g) & 0xff)
+    }
+
+    fn is_valid_key(k: u64) -> bool {
+        hash_bits(k) == (hash_numbers(key(k), gen(k)) & 0xff)
+    }
+
+    fn random_target(rnd: &mut Random) -> u64 {
+        match rnd.next_u32() % 10 {
+            // Seek to beginning
+            0 => make_key(0, 0),
+            // Seek to end
+            1 => make_key(K, 0),
+            // Seek to middle
+            _ => make_key(u64::from(rnd.next_u32()) % K, 0),
+        }
+    }
+
+    struct ConcurrentTest {
+        // Per-key generation
+        current: [AtomicU64; K as usize],
+        // SkipList is not protected by a lock. We just use a single writer
+        // thread to modify it.
+        list: SkipList<u64, OrdComparator>,
+    }
+
+    impl ConcurrentTest {
+        fn new() -> ConcurrentTest {
+            ConcurrentTest {
+                current: Default::default(),
+                list: SkipList::new(OrdComparator),
+            }
+        }
+
+        // REQUIRES: External synchronization
+        fn write_step(&self, rnd: &mut Random) {
+            let k = u64::from(rnd.next_u32()) % K;
+            let g = self.current[k as usize].load(atomic::Ordering::Acquire) + 1;
+            unsafe { self.list.insert(make_key(k, g)) };
+            self.current[k as usize].store(g, atomic::Ordering::Release);
+        }
+
+        fn read_step(&self, rnd: &mut Random) {
+            // Remember the initial committed state of the skiplist.
+            let initial_state: Vec<u64> = self
+                .current
+                .iter()
+                .map(|g| g.load(atomic::Ordering::Acquire))
+                .collect();
+
+            let mut pos = random_target(rnd);
+            let mut iter = self.list.iter();
+            iter.seek(&pos);
+            loop {
+                let current = if iter.valid() { iter.key() } else { make_key(K, 0) };
+                assert!(is_valid_key(current), "torn key {:#x}", current);
+                assert!(pos <= current, "should not go backwards");
+
+                // Verify that everything in [pos,current) was not present in
+                // initial_state.
+                while pos < current {
+                    assert!(key(pos) < K, "{:#x}", pos);
+
+                    // Note that generation 0 is never inserted, so it is ok
+                    // if <*,0,*> is missing.
+                    assert!(
+                        gen(pos) == 0 || gen(pos) > initial_state[key(pos) as usize],
+                        "key: {}; gen: {}; initgen: {}",
+                        key(pos),
+                        gen(pos),
+                        initial_state[key(pos) as usize]
+                    );
+
+                    // Advance to next key in the valid key space
+                    if key(pos) < key(current) {
+                        pos = make_key(key(pos) + 1, 0);
+                    } else {
+                        pos = make_key(key(pos), gen(pos) + 1);
+                    }
+                }
+
+                if !iter.valid() {
+                    break;
+                }
+
+                if rnd.next_u32() % 2 == 1 {
+                    iter.next();
+                    pos = make_key(key(pos), gen(pos) + 1);
+                } else {
+                    let new_target = random_target(rnd);
+                    if new_target > pos {
+                        pos = new_target;
+                        iter.seek(&new_target);
+                    }
+                }
+            }
+        }
+    }
+
+    // Simple test that does single-threaded testing of the ConcurrentTest
+    // scaffolding.
+    #[test]
+    fn concurrent_without_threads() {
+        let test = ConcurrentTest::new();
+        let mut rnd = Random::new(301);
+        for _ in 0..10000 {
+            test.read_step(&mut rnd);
+            test.write_step(&mut rnd);
+        }
+    }
+
+    fn run_concurrent(run: u32, readers: u32) {
+        const N: u32 = 100;
+        const SIZE: u32 = 1000;
+        let seed = 301 + run * 100;
+        for i in 0..N {
+            let test = ConcurrentTest::new();
+            let done = AtomicBool::new(false);
+            thread::scope(|s| {
+                for r in 0..readers {
+                    let (test, done) = (&test, &done);
+                    s.spawn(move || {
+                        let mut rnd = Random::new(seed + i * 1000 + r + 1);
+                        while !done.load(atomic::Ordering::Acquire) {
+                            test.read_step(&mut rnd);
+                        }
+                    });
+                }
+                let mut rnd = Random::new(seed + i * 1000);
+                for _ in 0..SIZE {
+                    test.write_step(&mut rnd);
+                }
+                done.store(true, atomic::Ordering::Release);
+            });
+        }
+    }
+
+    #[test]
+    fn concurrent1() {
+        run_concurrent(1, 1);
+    }
+
+    #[test]
+    fn concurrent2() {
+        run_concurrent(2, 2);
+    }
+
+    #[test]
+    fn concurrent3() {
+        run_concurrent(3, 4);
+    }
+}
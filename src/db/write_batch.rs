@@ -0,0 +1,483 @@
+//! `WriteBatch::rep` :=
+//!    sequence: fixed64
+//!    count: fixed32
+//!    data: record[count]
+//! record :=
+//!    kTypeValue varstring varstring         |
+//!    kTypeDeletion varstring
+//! varstring :=
+//!    len: varint32
+//!    data: uint8[len]
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use db::dbformat::{SequenceNumber, ValueType};
+use db::memtable::MemTable;
+use error::{Error, Result};
+use util::coding::{
+    put_length_prefixed_slice, try_encode_fixed32, try_encode_fixed64, DecodeError, Decoder,
+};
+
+// WriteBatch header has an 8-byte sequence number followed by a 4-byte count.
+const HEADER: usize = 12;
+
+/// Receives the operations stored in a `WriteBatch`, in insertion order.
+pub trait Handler {
+    fn put(&mut self, key: &[u8], value: &[u8]);
+    fn delete(&mut self, key: &[u8]);
+}
+
+/// A batch of updates that is applied atomically, serialized exactly like
+/// LevelDB's `WriteBatch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteBatch {
+    rep: Vec<u8>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch { rep: vec![0; HEADER] }
+    }
+
+    /// Stores the mapping "key->value" in the database.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` or `value` is 4 GiB or longer.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        let count = self.count();
+        self.set_count(count + 1);
+        self.rep.push(ValueType::Value as u8);
+        put_length_prefixed_slice(&mut self.rep, key).expect("WriteBatch key too long");
+        put_length_prefixed_slice(&mut self.rep, value).expect("WriteBatch value too long");
+    }
+
+    /// If the database contains a mapping for "key", erase it. Else do
+    /// nothing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is 4 GiB or longer.
+    pub fn delete(&mut self, key: &[u8]) {
+        let count = self.count();
+        self.set_count(count + 1);
+        self.rep.push(ValueType::Deletion as u8);
+        put_length_prefixed_slice(&mut self.rep, key).expect("WriteBatch key too long");
+    }
+
+    /// Clears all updates buffered in this batch.
+    pub fn clear(&mut self) {
+        self.rep.clear();
+        self.rep.resize(HEADER, 0);
+    }
+
+    /// The size of the database changes caused by this batch.
+    ///
+    /// This number is tied to implementation details, and may change across
+    /// releases. It is intended for LevelDB usage metrics.
+    pub fn approximate_size(&self) -> usize {
+        self.rep.len()
+    }
+
+    /// Copies the operations in `source` to this batch.
+    ///
+    /// This runs in O(source size) time. However, the constant factor is
+    /// better than calling `iterate()` over the source batch with a
+    /// `Handler` that replicates the operations into this batch.
+    pub fn append(&mut self, source: &WriteBatch) {
+        let count = self.count() + source.count();
+        self.set_count(count);
+        assert!(source.rep.len() >= HEADER);
+        self.rep.extend_from_slice(&source.rep[HEADER..]);
+    }
+
+    /// Replays the operations in this batch into `handler`.
+    ///
+    /// Fails with `Corruption` naming the offending record and its byte
+    /// offset if the batch is malformed. Records before the bad one have
+    /// already been passed to `handler` by then.
+    pub fn iterate(&self, handler: &mut dyn Handler) -> Result<()> {
+        let mut input = Decoder::new(&self.rep);
+        input.read_fixed64("sequence")?;
+        let count = input.read_fixed32("count")?;
+        let mut found: u64 = 0;
+        while !input.is_empty() {
+            let record = found;
+            let offset = input.position();
+            found += 1;
+            let tag = input.read_u8("tag")?;
+            match ValueType::from_u8(tag) {
+                Some(ValueType::Value) => {
+                    let key = input
+                        .read_length_prefixed_slice("key")
+                        .map_err(|e| bad_record("Put", record, offset, e))?;
+                    let value = input
+                        .read_length_prefixed_slice("value")
+                        .map_err(|e| bad_record("Put", record, offset, e))?;
+                    handler.put(key, value);
+                }
+                Some(ValueType::Deletion) => {
+                    let key = input
+                        .read_length_prefixed_slice("key")
+                        .map_err(|e| bad_record("Delete", record, offset, e))?;
+                    handler.delete(key);
+                }
+                None => {
+                    return Err(Error::corruption(format!(
+                        "unknown WriteBatch tag {} in record {} at offset {}",
+                        tag, record, offset
+                    )))
+                }
+            }
+        }
+        if found != u64::from(count) {
+            Err(Error::corruption(format!(
+                "WriteBatch has wrong count: header says {} but found {} records ending at offset {}",
+                count,
+                found,
+                self.rep.len()
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Applies the operations in this batch to `memtable`, numbering them
+    /// from `sequence()` upwards.
+    pub fn insert_into(&self, memtable: &MemTable) -> Result<()> {
+        let mut inserter = MemTableInserter { sequence: self.sequence(), memtable };
+        self.iterate(&mut inserter)
+    }
+
+    /// Returns the number of entries in the batch.
+    pub fn count(&self) -> u32 {
+        LittleEndian::read_u32(&self.rep[8..])
+    }
+
+    /// Sets the count for the number of entries in the batch.
+    pub fn set_count(&mut self, n: u32) {
+        try_encode_fixed32(&mut self.rep[8..], n);
+    }
+
+    /// Returns the sequence number for the start of this batch.
+    pub fn sequence(&self) -> SequenceNumber {
+        LittleEndian::read_u64(&self.rep)
+    }
+
+    /// Stores the specified number as the sequence number for the start of
+    /// this batch.
+    pub fn set_sequence(&mut self, sequence: SequenceNumber) {
+        try_encode_fixed64(&mut self.rep, sequence);
+    }
+
+    /// The serialized representation of the batch.
+    pub fn contents(&self) -> &[u8] {
+        &self.rep
+    }
+
+    /// Replaces the batch with the serialized representation `contents`,
+    /// for example one written by C++ LevelDB.
+    ///
+    /// Fails with `Corruption`, leaving the batch unchanged, if `contents`
+    /// is shorter than the 12 byte header. The records themselves are only
+    /// checked by `iterate`.
+    pub fn set_contents(&mut self, contents: &[u8]) -> Result<()> {
+        if contents.len() < HEADER {
+            return Err(Error::corruption("malformed WriteBatch (too small)"));
+        }
+        self.rep.clear();
+        self.rep.extend_from_slice(contents);
+        Ok(())
+    }
+}
+
+impl Default for WriteBatch {
+    fn default() -> WriteBatch {
+        WriteBatch::new()
+    }
+}
+
+fn bad_record(op: &str, record: u64, offset: usize, e: DecodeError) -> Error {
+    Error::corruption(format!(
+        "bad WriteBatch {} in record {} at offset {}: {}",
+        op, record, offset, e
+    ))
+}
+
+struct MemTableInserter<'a> {
+    sequence: SequenceNumber,
+    memtable: &'a MemTable,
+}
+
+impl<'a> Handler for MemTableInserter<'a> {
+    fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.memtable.add(self.sequence, ValueType::Value, key, value);
+        self.sequence += 1;
+    }
+
+    fn delete(&mut self, key: &[u8]) {
+        self.memtable.add(self.sequence, ValueType::Deletion, key, &[]);
+        self.sequence += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use db::dbformat::{parse_internal_key, InternalKeyComparator, LookupKey};
+    use db::memtable::LookupResult;
+    use util::comparator::BytewiseComparator;
+    use util::random::Random;
+
+    #[derive(Default)]
+    struct Recorder {
+        ops: Vec<String>,
+    }
+
+    impl Handler for Recorder {
+        fn put(&mut self, key: &[u8], value: &[u8]) {
+            self.ops.push(format!(
+                "Put({}, {})",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(value)
+            ));
+        }
+
+        fn delete(&mut self, key: &[u8]) {
+            self.ops.push(format!("Delete({})", String::from_utf8_lossy(key)));
+        }
+    }
+
+    fn print_contents(b: &WriteBatch) -> (Vec<String>, Result<()>) {
+        let mut recorder = Recorder::default();
+        let result = b.iterate(&mut recorder);
+        (recorder.ops, result)
+    }
+
+    // Records start at offsets 12, 21 and 26; the batch is 35 bytes long.
+    fn sample() -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        batch.put(b"foo", b"bar");
+        batch.delete(b"box");
+        batch.put(b"baz", b"boo");
+        batch.set_sequence(100);
+        batch
+    }
+
+    fn corrupted(contents: &[u8]) -> (Vec<String>, String) {
+        let mut batch = WriteBatch::new();
+        batch.set_contents(contents).unwrap();
+        let (ops, result) = print_contents(&batch);
+        let err = result.unwrap_err();
+        assert!(err.is_corruption());
+        (ops, err.message().to_string())
+    }
+
+    #[test]
+    fn empty() {
+        let batch = WriteBatch::new();
+        assert_eq!(print_contents(&batch).0, Vec::<String>::new());
+        assert_eq!(batch.count(), 0);
+    }
+
+    #[test]
+    fn multiple() {
+        let batch = sample();
+        assert_eq!(batch.sequence(), 100);
+        assert_eq!(batch.count(), 3);
+        assert_eq!(batch.approximate_size(), 35);
+        let (ops, result) = print_contents(&batch);
+        result.unwrap();
+        assert_eq!(ops, ["Put(foo, bar)", "Delete(box)", "Put(baz, boo)"]);
+
+        let expected: &[u8] = &[
+            // fixed64 sequence
+            100, 0, 0, 0, 0, 0, 0, 0,
+            // fixed32 count
+            3, 0, 0, 0,
+            // tag, then varint32-prefixed key and value
+            1, 3, b'f', b'o', b'o', 3, b'b', b'a', b'r',
+            0, 3, b'b', b'o', b'x',
+            1, 3, b'b', b'a', b'z', 3, b'b', b'o', b'o',
+        ];
+        assert_eq!(batch.contents(), expected);
+    }
+
+    #[test]
+    fn leveldb_contents() {
+        // What C++ LevelDB writes for Put("key", "value"), Delete("gone") and
+        // a Put of a 130-byte value at sequence 0x0102030405060708.
+        let mut contents = vec![
+            0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+            0x03, 0x00, 0x00, 0x00,
+            0x01, 0x03, b'k', b'e', b'y', 0x05, b'v', b'a', b'l', b'u', b'e',
+            0x00, 0x04, b'g', b'o', b'n', b'e',
+            0x01, 0x01, b'x', 0x82, 0x01,
+        ];
+        contents.extend_from_slice(&[b'y'; 130]);
+
+        let mut batch = WriteBatch::new();
+        batch.set_contents(&contents).unwrap();
+        assert_eq!(batch.sequence(), 0x0102_0304_0506_0708);
+        assert_eq!(batch.count(), 3);
+        let (ops, result) = print_contents(&batch);
+        result.unwrap();
+        assert_eq!(
+            ops,
+            [
+                "Put(key, value)".to_string(),
+                "Delete(gone)".to_string(),
+                format!("Put(x, {})", "y".repeat(130)),
+            ]
+        );
+
+        let mut rebuilt = WriteBatch::new();
+        rebuilt.put(b"key", b"value");
+        rebuilt.delete(b"gone");
+        rebuilt.put(b"x", &[b'y'; 130]);
+        rebuilt.set_sequence(0x0102_0304_0506_0708);
+        assert_eq!(rebuilt.contents(), &contents[..]);
+    }
+
+    #[test]
+    fn append() {
+        let mut b1 = WriteBatch::new();
+        let mut b2 = WriteBatch::new();
+        b1.set_sequence(200);
+        b2.set_sequence(300);
+        b1.append(&b2);
+        assert_eq!(print_contents(&b1).0, Vec::<String>::new());
+        b2.put(b"a", b"va");
+        b1.append(&b2);
+        assert_eq!(print_contents(&b1).0, ["Put(a, va)"]);
+        b2.clear();
+        b2.put(b"b", b"vb");
+        b1.append(&b2);
+        b2.delete(b"foo");
+        b1.append(&b2);
+        assert_eq!(
+            print_contents(&b1).0,
+            ["Put(a, va)", "Put(b, vb)", "Put(b, vb)", "Delete(foo)"]
+        );
+        assert_eq!(b1.sequence(), 200);
+        assert_eq!(b1.count(), 4);
+    }
+
+    #[test]
+    fn wrong_count() {
+        let mut batch = sample();
+        batch.set_count(4);
+        let (ops, message) = corrupted(batch.contents());
+        assert_eq!(ops.len(), 3);
+        assert_eq!(
+            message,
+            "WriteBatch has wrong count: header says 4 but found 3 records ending at offset 35"
+        );
+    }
+
+    #[test]
+    fn truncated_value() {
+        let batch = sample();
+        let (ops, message) = corrupted(&batch.contents()[..33]);
+        assert_eq!(ops, ["Put(foo, bar)", "Delete(box)"]);
+        assert_eq!(
+            message,
+            "bad WriteBatch Put in record 2 at offset 26: truncated value at offset 31"
+        );
+    }
+
+    #[test]
+    fn truncated_key() {
+        let batch = sample();
+        let (ops, message) = corrupted(&batch.contents()[..24]);
+        assert_eq!(ops, ["Put(foo, bar)"]);
+        assert_eq!(
+            message,
+            "bad WriteBatch Delete in record 1 at offset 21: truncated key at offset 22"
+        );
+    }
+
+    #[test]
+    fn unknown_tag() {
+        let mut contents = sample().contents().to_vec();
+        contents[21] = 7;
+        let (ops, message) = corrupted(&contents);
+        assert_eq!(ops, ["Put(foo, bar)"]);
+        assert_eq!(message, "unknown WriteBatch tag 7 in record 1 at offset 21");
+    }
+
+    #[test]
+    fn set_contents_too_small() {
+        let mut batch = sample();
+        let err = batch.set_contents(&[0; 11]).unwrap_err();
+        assert!(err.is_corruption());
+        assert_eq!(batch, sample());
+    }
+
+    #[test]
+    fn fuzz_truncate_and_flip() {
+        let mut rnd = Random::new(301);
+        for _ in 0..50_000 {
+            let mut batch = WriteBatch::new();
+            for _ in 0..rnd.uniform(8) {
+                let key = vec![b'k'; rnd.skewed(8) as usize];
+                if rnd.one_in(3) {
+                    batch.delete(&key);
+                } else {
+                    batch.put(&key, &vec![b'v'; rnd.skewed(10) as usize]);
+                }
+            }
+            let mut contents = batch.contents().to_vec();
+            if rnd.one_in(2) {
+                let len = rnd.uniform(contents.len() as u32 + 1) as usize;
+                contents.truncate(len);
+            }
+            for _ in 0..rnd.uniform(4) {
+                if contents.is_empty() {
+                    break;
+                }
+                let i = rnd.uniform(contents.len() as u32) as usize;
+                contents[i] ^= 1 << rnd.uniform(8);
+            }
+
+            let mut mutated = WriteBatch::new();
+            if mutated.set_contents(&contents).is_ok() {
+                // Only the absence of a panic matters here.
+                let _ = mutated.iterate(&mut Recorder::default());
+            }
+        }
+    }
+
+    #[test]
+    fn insert_into() {
+        let mut batch = sample();
+        let mem = MemTable::new(InternalKeyComparator::new(Arc::new(BytewiseComparator)));
+        batch.insert_into(&mem).unwrap();
+
+        // Operations are numbered from sequence() in batch order.
+        let mut ops = Vec::new();
+        let mut iter = mem.iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let parsed = parse_internal_key(iter.key()).unwrap();
+            ops.push(format!(
+                "{:?}({}, {})@{}",
+                parsed.value_type,
+                String::from_utf8_lossy(parsed.user_key),
+                String::from_utf8_lossy(iter.value()),
+                parsed.sequence
+            ));
+            iter.next();
+        }
+        assert_eq!(ops, ["Value(baz, boo)@102", "Deletion(box, )@101", "Value(foo, bar)@100"]);
+        assert_eq!(mem.get(&LookupKey::new(b"foo", 100)), LookupResult::Found(b"bar"));
+        assert_eq!(mem.get(&LookupKey::new(b"box", 200)), LookupResult::Deleted);
+
+        // A corrupt batch stops at the bad record.
+        batch.set_count(4);
+        let mem = MemTable::new(InternalKeyComparator::new(Arc::new(BytewiseComparator)));
+        assert!(batch.insert_into(&mem).unwrap_err().is_corruption());
+    }
+}
@@ -0,0 +1,554 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use env::{is_manifest, read_file_to_vec, write_string_to_file, Env, FileLock, RandomAccessFile, SequentialFile, WritableFile};
+use error::{Error, Result};
+
+// How much of a file has been written and how much of that is known to
+// have reached stable storage.
+#[derive(Clone, Copy)]
+struct FileState {
+    pos: u64,
+    pos_at_last_sync: u64,
+}
+
+// Counts down to an injected failure; `None` when disarmed.
+#[derive(Default)]
+struct Countdown(Option<u64>);
+
+impl Countdown {
+    // Returns true if the current operation should fail.
+    fn tick(&mut self) -> bool {
+        match self.0 {
+            Some(1) => {
+                self.0 = None;
+                true
+            }
+            Some(ref mut n) => {
+                *n -= 1;
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+struct State {
+    files: HashMap<PathBuf, FileState>,
+    // Files whose directory entry would not survive a crash yet.
+    new_files_since_last_dir_sync: HashSet<PathBuf>,
+    filesystem_active: bool,
+    fail_write: Countdown,
+    fail_sync: Countdown,
+    fail_rename: Countdown,
+    space_left: Option<u64>,
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            files: HashMap::new(),
+            new_files_since_last_dir_sync: HashSet::new(),
+            filesystem_active: true,
+            fail_write: Countdown::default(),
+            fail_sync: Countdown::default(),
+            fail_rename: Countdown::default(),
+            space_left: None,
+        }
+    }
+
+    // A synced MANIFEST implies a synced directory, as in `PosixWritableFile`.
+    fn sync_dir(&mut self, dir: Option<&Path>) {
+        self.new_files_since_last_dir_sync
+            .retain(|name| name.parent() != dir);
+    }
+}
+
+fn check_active(state: &State, name: &Path) -> Result<()> {
+    if state.filesystem_active {
+        Ok(())
+    } else {
+        Err(Error::io_error(format!("{}: filesystem is inactive", name.display())))
+    }
+}
+
+fn injected(what: &str, name: &Path) -> Error {
+    Error::io_error(format!("{}: injected {} error", name.display(), what))
+}
+
+struct FaultInjectionWritableFile {
+    name: PathBuf,
+    is_manifest: bool,
+    target: Box<dyn WritableFile>,
+    state: Arc<Mutex<State>>,
+}
+
+impl WritableFile for FaultInjectionWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.fail_write.tick() {
+            return Err(injected("write", &self.name));
+        }
+        if let Some(space_left) = state.space_left {
+            if (data.len() as u64) > space_left {
+                return Err(Error::io_error(format!(
+                    "{}: No space left on device",
+                    self.name.display()
+                )));
+            }
+            state.space_left = Some(space_left - data.len() as u64);
+        }
+        self.target.append(data)?;
+        if let Some(file) = state.files.get_mut(&self.name) {
+            file.pos += data.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.target.close()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.target.flush()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.fail_sync.tick() {
+            return Err(injected("sync", &self.name));
+        }
+        if !state.filesystem_active {
+            // The crash already happened; nothing reaches the disk any more.
+            return Ok(());
+        }
+        self.target.sync()?;
+        if let Some(file) = state.files.get_mut(&self.name) {
+            file.pos_at_last_sync = file.pos;
+        }
+        if self.is_manifest {
+            state.sync_dir(self.name.parent());
+        }
+        Ok(())
+    }
+}
+
+/// An `Env` wrapper that simulates crashes and injects I/O errors.
+///
+/// Every file written through this env remembers how much of it has been
+/// synced. `crash()` throws away everything that would not have survived a
+/// power loss: unsynced tail data, and files created since the last sync
+/// of their directory. In addition, the Nth write, sync or rename can be
+/// made to fail, and a limit can be put on the number of bytes that may
+/// still be appended before the disk is full.
+pub struct FaultInjectionEnv {
+    target: Arc<dyn Env>,
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultInjectionEnv {
+    pub fn new(target: Arc<dyn Env>) -> FaultInjectionEnv {
+        FaultInjectionEnv {
+            target,
+            state: Arc::new(Mutex::new(State::new())),
+        }
+    }
+
+    pub fn target(&self) -> &Arc<dyn Env> {
+        &self.target
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// While the filesystem is inactive, syncs are silently ignored and
+    /// creating, renaming or removing files fails.
+    pub fn set_filesystem_active(&self, active: bool) {
+        self.state().filesystem_active = active;
+    }
+
+    pub fn is_filesystem_active(&self) -> bool {
+        self.state().filesystem_active
+    }
+
+    /// Makes the `n`th append from now on fail with an I/O error, `n`
+    /// counting from 1. The failed append writes nothing.
+    pub fn fail_nth_write(&self, n: u64) {
+        assert!(n > 0);
+        self.state().fail_write = Countdown(Some(n));
+    }
+
+    /// Makes the `n`th sync from now on fail with an I/O error.
+    pub fn fail_nth_sync(&self, n: u64) {
+        assert!(n > 0);
+        self.state().fail_sync = Countdown(Some(n));
+    }
+
+    /// Makes the `n`th rename from now on fail with an I/O error.
+    pub fn fail_nth_rename(&self, n: u64) {
+        assert!(n > 0);
+        self.state().fail_rename = Countdown(Some(n));
+    }
+
+    /// Limits the number of bytes that can still be appended; appends that
+    /// do not fit fail as if the disk were full. `None` lifts the limit.
+    pub fn set_space_left(&self, bytes: Option<u64>) {
+        self.state().space_left = bytes;
+    }
+
+    /// Truncates every tracked file to the length it had when it was last
+    /// synced.
+    pub fn drop_unsynced_file_data(&self) -> Result<()> {
+        let files: Vec<(PathBuf, FileState)> = self
+            .state()
+            .files
+            .iter()
+            .filter(|&(_, f)| f.pos > f.pos_at_last_sync)
+            .map(|(name, f)| (name.clone(), *f))
+            .collect();
+        for (name, file) in files {
+            if !self.target.file_exists(&name) {
+                continue;
+            }
+            let mut data = read_file_to_vec(&*self.target, &name)?;
+            data.truncate(file.pos_at_last_sync as usize);
+            write_string_to_file(&*self.target, &data, &name, true)?;
+            if let Some(f) = self.state().files.get_mut(&name) {
+                f.pos = f.pos_at_last_sync;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes every file created since its directory was last synced.
+    pub fn remove_files_created_after_last_dir_sync(&self) -> Result<()> {
+        let names: Vec<PathBuf> = self
+            .state()
+            .new_files_since_last_dir_sync
+            .drain()
+            .collect();
+        for name in names {
+            if self.target.file_exists(&name) {
+                self.target.remove_file(&name)?;
+            }
+            self.state().files.remove(&name);
+        }
+        Ok(())
+    }
+
+    /// Simulates a crash followed by a restart: unsynced data and
+    /// unsynced files are lost, and all tracking and injected faults are
+    /// reset.
+    pub fn crash(&self) -> Result<()> {
+        self.remove_files_created_after_last_dir_sync()?;
+        self.drop_unsynced_file_data()?;
+        self.reset_state();
+        Ok(())
+    }
+
+    /// Forgets all tracked files and disarms every injected fault.
+    pub fn reset_state(&self) {
+        *self.state() = State::new();
+    }
+
+    fn wrap(&self, name: &Path, target: Box<dyn WritableFile>) -> Box<dyn WritableFile> {
+        Box::new(FaultInjectionWritableFile {
+            name: name.to_path_buf(),
+            is_manifest: is_manifest(name),
+            target,
+            state: self.state.clone(),
+        })
+    }
+}
+
+impl Env for FaultInjectionEnv {
+    fn new_sequential_file(&self, name: &Path) -> Result<Box<dyn SequentialFile>> {
+        self.target.new_sequential_file(name)
+    }
+
+    fn new_random_access_file(&self, name: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        self.target.new_random_access_file(name)
+    }
+
+    fn new_writable_file(&self, name: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state();
+        check_active(&state, name)?;
+        let file = self.target.new_writable_file(name)?;
+        state.files.insert(name.to_path_buf(), FileState { pos: 0, pos_at_last_sync: 0 });
+        state.new_files_since_last_dir_sync.insert(name.to_path_buf());
+        drop(state);
+        Ok(self.wrap(name, file))
+    }
+
+    fn new_appendable_file(&self, name: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state();
+        check_active(&state, name)?;
+        let existed = self.target.file_exists(name);
+        let file = self.target.new_appendable_file(name)?;
+        if !state.files.contains_key(name) {
+            // Data that was already there is assumed to be durable.
+            let size = self.target.get_file_size(name)?;
+            state.files.insert(name.to_path_buf(), FileState { pos: size, pos_at_last_sync: size });
+        }
+        if !existed {
+            state.new_files_since_last_dir_sync.insert(name.to_path_buf());
+        }
+        drop(state);
+        Ok(self.wrap(name, file))
+    }
+
+    fn file_exists(&self, name: &Path) -> bool {
+        self.target.file_exists(name)
+    }
+
+    fn get_children(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        self.target.get_children(dir)
+    }
+
+    fn remove_file(&self, name: &Path) -> Result<()> {
+        let mut state = self.state();
+        check_active(&state, name)?;
+        self.target.remove_file(name)?;
+        state.files.remove(name);
+        state.new_files_since_last_dir_sync.remove(name);
+        Ok(())
+    }
+
+    fn create_dir(&self, name: &Path) -> Result<()> {
+        self.target.create_dir(name)
+    }
+
+    fn remove_dir(&self, name: &Path) -> Result<()> {
+        self.target.remove_dir(name)
+    }
+
+    fn get_file_size(&self, name: &Path) -> Result<u64> {
+        self.target.get_file_size(name)
+    }
+
+    fn rename_file(&self, src: &Path, target: &Path) -> Result<()> {
+        let mut state = self.state();
+        check_active(&state, src)?;
+        if state.fail_rename.tick() {
+            return Err(injected("rename", src));
+        }
+        self.target.rename_file(src, target)?;
+        // whatever was tracked under `target` has been replaced
+        state.files.remove(target);
+        state.new_files_since_last_dir_sync.remove(target);
+        if let Some(file) = state.files.remove(src) {
+            state.files.insert(target.to_path_buf(), file);
+        }
+        if state.new_files_since_last_dir_sync.remove(src) {
+            state.new_files_since_last_dir_sync.insert(target.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn lock_file(&self, name: &Path) -> Result<Box<dyn FileLock>> {
+        self.target.lock_file(name)
+    }
+
+    fn unlock_file(&self, lock: Box<dyn FileLock>) -> Result<()> {
+        self.target.unlock_file(lock)
+    }
+
+    fn schedule(&self, function: Box<dyn FnOnce() + Send>) {
+        self.target.schedule(function)
+    }
+
+    fn start_thread(&self, function: Box<dyn FnOnce() + Send>) {
+        self.target.start_thread(function)
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.target.now_micros()
+    }
+
+    fn sleep_for_microseconds(&self, micros: u64) {
+        self.target.sleep_for_microseconds(micros)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env::MemEnv;
+
+    fn setup() -> (Arc<MemEnv>, FaultInjectionEnv) {
+        let mem = Arc::new(MemEnv::new());
+        let env = FaultInjectionEnv::new(mem.clone());
+        (mem, env)
+    }
+
+    fn write(env: &FaultInjectionEnv, name: &str, data: &[u8], sync: bool) {
+        let mut file = env.new_writable_file(Path::new(name)).unwrap();
+        file.append(data).unwrap();
+        if sync {
+            file.sync().unwrap();
+        }
+    }
+
+    // Syncing a MANIFEST makes the entries in its directory durable.
+    fn sync_dir(env: &FaultInjectionEnv) {
+        write(env, "/db/MANIFEST-000001", b"", true);
+    }
+
+    #[test]
+    fn crash_truncates_to_last_sync() {
+        let (mem, env) = setup();
+        let name = Path::new("/db/000003.log");
+        let mut file = env.new_writable_file(name).unwrap();
+        sync_dir(&env);
+        file.append(b"synced").unwrap();
+        file.sync().unwrap();
+        file.append(b" lost").unwrap();
+        assert_eq!(mem.file_contents(name).unwrap(), b"synced lost");
+
+        env.crash().unwrap();
+        assert_eq!(mem.file_contents(name).unwrap(), b"synced");
+    }
+
+    #[test]
+    fn crash_removes_files_created_after_dir_sync() {
+        let (mem, env) = setup();
+        write(&env, "/db/000004.ldb", b"table", true);
+        env.crash().unwrap();
+        assert!(!mem.file_exists(Path::new("/db/000004.ldb")));
+
+        write(&env, "/db/000005.ldb", b"table", true);
+        sync_dir(&env);
+        env.crash().unwrap();
+        assert_eq!(mem.file_contents(Path::new("/db/000005.ldb")).unwrap(), b"table");
+    }
+
+    #[test]
+    fn appendable_file_keeps_existing_data() {
+        let (mem, env) = setup();
+        write_string_to_file(&*mem, b"old", Path::new("/db/LOG"), false).unwrap();
+        let mut file = env.new_appendable_file(Path::new("/db/LOG")).unwrap();
+        file.append(b" new").unwrap();
+        env.crash().unwrap();
+        assert_eq!(mem.file_contents(Path::new("/db/LOG")).unwrap(), b"old");
+    }
+
+    #[test]
+    fn inactive_filesystem_ignores_syncs() {
+        let (mem, env) = setup();
+        let name = Path::new("/db/000003.log");
+        let mut file = env.new_writable_file(name).unwrap();
+        sync_dir(&env);
+        file.append(b"a").unwrap();
+        file.sync().unwrap();
+
+        env.set_filesystem_active(false);
+        assert!(!env.is_filesystem_active());
+        file.append(b"b").unwrap();
+        file.sync().unwrap();
+        assert!(env.new_writable_file(Path::new("/db/x")).is_err());
+        assert!(env.rename_file(name, Path::new("/db/x")).is_err());
+        assert!(env.remove_file(name).is_err());
+
+        env.crash().unwrap();
+        assert!(env.is_filesystem_active());
+        assert_eq!(mem.file_contents(name).unwrap(), b"a");
+    }
+
+    #[test]
+    fn fail_nth_write() {
+        let (mem, env) = setup();
+        let name = Path::new("/db/f");
+        let mut file = env.new_writable_file(name).unwrap();
+        env.fail_nth_write(3);
+        file.append(b"1").unwrap();
+        file.append(b"2").unwrap();
+        let err = file.append(b"3").unwrap_err();
+        assert!(err.is_io_error());
+        assert_eq!(err.message(), "/db/f: injected write error");
+        file.append(b"4").unwrap();
+        assert_eq!(mem.file_contents(name).unwrap(), b"124");
+    }
+
+    #[test]
+    fn fail_nth_sync() {
+        let (mem, env) = setup();
+        let name = Path::new("/db/f");
+        let mut file = env.new_writable_file(name).unwrap();
+        sync_dir(&env);
+        env.fail_nth_sync(2);
+        file.append(b"a").unwrap();
+        file.sync().unwrap();
+        file.append(b"b").unwrap();
+        assert!(file.sync().unwrap_err().is_io_error());
+
+        // the failed sync did not make "b" durable
+        env.crash().unwrap();
+        assert_eq!(mem.file_contents(name).unwrap(), b"a");
+    }
+
+    #[test]
+    fn fail_nth_rename() {
+        let (mem, env) = setup();
+        write(&env, "/db/a", b"a", true);
+        write(&env, "/db/b", b"b", true);
+        env.fail_nth_rename(1);
+        let err = env.rename_file(Path::new("/db/a"), Path::new("/db/c")).unwrap_err();
+        assert!(err.is_io_error());
+        assert!(mem.file_exists(Path::new("/db/a")));
+        env.rename_file(Path::new("/db/b"), Path::new("/db/c")).unwrap();
+        assert_eq!(mem.file_contents(Path::new("/db/c")).unwrap(), b"b");
+    }
+
+    #[test]
+    fn rename_over_tracked_file() {
+        let (mem, env) = setup();
+        // "/db/CURRENT" is tracked with one synced byte and more unsynced ones.
+        let mut current = env.new_writable_file(Path::new("/db/CURRENT")).unwrap();
+        sync_dir(&env);
+        current.append(b"1").unwrap();
+        current.sync().unwrap();
+        current.append(b"23456").unwrap();
+
+        // The replacement was written by someone else and is not tracked.
+        write_string_to_file(&*mem, b"MANIFEST-000002\n", Path::new("/db/tmp"), true).unwrap();
+        env.rename_file(Path::new("/db/tmp"), Path::new("/db/CURRENT")).unwrap();
+
+        env.crash().unwrap();
+        assert_eq!(
+            mem.file_contents(Path::new("/db/CURRENT")).unwrap(),
+            b"MANIFEST-000002\n"
+        );
+    }
+
+    #[test]
+    fn rename_keeps_sync_state() {
+        let (mem, env) = setup();
+        let mut file = env.new_writable_file(Path::new("/db/tmp")).unwrap();
+        file.append(b"synced").unwrap();
+        file.sync().unwrap();
+        env.rename_file(Path::new("/db/tmp"), Path::new("/db/CURRENT")).unwrap();
+
+        // the new name is lost too until the directory is synced
+        env.crash().unwrap();
+        assert!(!mem.file_exists(Path::new("/db/CURRENT")));
+    }
+
+    #[test]
+    fn set_space_left() {
+        let (mem, env) = setup();
+        let name = Path::new("/db/f");
+        let mut file = env.new_writable_file(name).unwrap();
+        env.set_space_left(Some(5));
+        file.append(b"abc").unwrap();
+        let err = file.append(b"def").unwrap_err();
+        assert!(err.is_io_error());
+        assert_eq!(err.message(), "/db/f: No space left on device");
+        file.append(b"de").unwrap();
+        assert!(file.append(b"f").is_err());
+        env.set_space_left(None);
+        file.append(b"f").unwrap();
+        assert_eq!(mem.file_contents(name).unwrap(), b"abcdef");
+    }
+}
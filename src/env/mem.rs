@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use env::{default_env, Env, FileLock, RandomAccessFile, SequentialFile, WritableFile};
+use error::{Error, Result};
+
+// The contents of one in-memory file, shared by every handle opened on it.
+#[derive(Default)]
+struct FileState {
+    data: RwLock<Vec<u8>>,
+}
+
+impl FileState {
+    fn size(&self) -> u64 {
+        self.data.read().unwrap().len() as u64
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let data = self.data.read().unwrap();
+        if offset > data.len() as u64 {
+            return Err(Error::io_error("Offset greater than file size."));
+        }
+        let offset = offset as usize;
+        let n = buf.len().min(data.len() - offset);
+        buf[..n].copy_from_slice(&data[offset..offset + n]);
+        Ok(n)
+    }
+}
+
+struct MemSequentialFile {
+    file: Arc<FileState>,
+    pos: u64,
+}
+
+impl SequentialFile for MemSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.file.read(self.pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> {
+        let size = self.file.size();
+        if self.pos > size {
+            return Err(Error::io_error(format!(
+                "read position {} is past the end of the file ({} bytes)",
+                self.pos, size
+            )));
+        }
+        self.pos += n.min(size - self.pos);
+        Ok(())
+    }
+}
+
+struct MemRandomAccessFile {
+    file: Arc<FileState>,
+}
+
+impl RandomAccessFile for MemRandomAccessFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.file.read(offset, buf)
+    }
+}
+
+struct MemWritableFile {
+    file: Arc<FileState>,
+}
+
+impl WritableFile for MemWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.file.data.write().unwrap().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct MemFileLock {
+    name: PathBuf,
+}
+
+impl FileLock for MemFileLock {
+    fn name(&self) -> &Path {
+        &self.name
+    }
+}
+
+#[derive(Default)]
+struct State {
+    files: HashMap<PathBuf, Arc<FileState>>,
+    dirs: HashSet<PathBuf>,
+    locks: HashSet<PathBuf>,
+}
+
+/// An `Env` that keeps all files in memory and delegates everything that is
+/// not file system related (threads, time) to a base `Env`.
+///
+/// Files opened before a rename or removal keep seeing the old contents,
+/// like on a POSIX file system.
+pub struct MemEnv {
+    base: Arc<dyn Env>,
+    state: Mutex<State>,
+}
+
+impl MemEnv {
+    /// Creates an in-memory env that uses `default_env()` for threads and
+    /// time.
+    pub fn new() -> MemEnv {
+        MemEnv::with_base(default_env())
+    }
+
+    pub fn with_base(base: Arc<dyn Env>) -> MemEnv {
+        MemEnv { base, state: Mutex::new(State::default()) }
+    }
+
+    /// Returns a copy of the current contents of the named file.
+    pub fn file_contents(&self, name: &Path) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .get(name)
+            .map(|f| f.data.read().unwrap().clone())
+    }
+
+    /// Returns a copy of every file in the env, keyed by name.
+    pub fn snapshot(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .iter()
+            .map(|(name, f)| (name.clone(), f.data.read().unwrap().clone()))
+            .collect()
+    }
+
+    fn file(&self, name: &Path) -> Result<Arc<FileState>> {
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .get(name)
+            .cloned()
+            .ok_or_else(|| not_found(name))
+    }
+}
+
+impl Default for MemEnv {
+    fn default() -> MemEnv {
+        MemEnv::new()
+    }
+}
+
+fn not_found(name: &Path) -> Error {
+    Error::not_found(format!("{}: File not found", name.display()))
+}
+
+impl Env for MemEnv {
+    fn new_sequential_file(&self, name: &Path) -> Result<Box<dyn SequentialFile>> {
+        let file = self.file(name)?;
+        Ok(Box::new(MemSequentialFile { file, pos: 0 }))
+    }
+
+    fn new_random_access_file(&self, name: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        let file = self.file(name)?;
+        Ok(Box::new(MemRandomAccessFile { file }))
+    }
+
+    fn new_writable_file(&self, name: &Path) -> Result<Box<dyn WritableFile>> {
+        let file = Arc::new(FileState::default());
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(name.to_path_buf(), file.clone());
+        Ok(Box::new(MemWritableFile { file }))
+    }
+
+    fn new_appendable_file(&self, name: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut state = self.state.lock().unwrap();
+        let file = state
+            .files
+            .entry(name.to_path_buf())
+            .or_default()
+            .clone();
+        Ok(Box::new(MemWritableFile { file }))
+    }
+
+    fn file_exists(&self, name: &Path) -> bool {
+        self.state.lock().unwrap().files.contains_key(name)
+    }
+
+    fn get_children(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let state = self.state.lock().unwrap();
+        let children = state
+            .files
+            .keys()
+            .chain(state.dirs.iter())
+            .filter(|name| name.parent() == Some(dir))
+            .filter_map(|name| name.file_name())
+            .map(PathBuf::from)
+            .collect();
+        Ok(children)
+    }
+
+    fn remove_file(&self, name: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .files
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| not_found(name))
+    }
+
+    fn create_dir(&self, name: &Path) -> Result<()> {
+        self.state.lock().unwrap().dirs.insert(name.to_path_buf());
+        Ok(())
+    }
+
+    fn remove_dir(&self, name: &Path) -> Result<()> {
+        self.state.lock().unwrap().dirs.remove(name);
+        Ok(())
+    }
+
+    fn get_file_size(&self, name: &Path) -> Result<u64> {
+        self.file(name).map(|f| f.size())
+    }
+
+    fn rename_file(&self, src: &Path, target: &Path) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let file = state.files.remove(src).ok_or_else(|| not_found(src))?;
+        state.files.insert(target.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn lock_file(&self, name: &Path) -> Result<Box<dyn FileLock>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.locks.insert(name.to_path_buf()) {
+            return Err(Error::io_error(format!(
+                "lock {}: already held by process",
+                name.display()
+            )));
+        }
+        // like a real file system, locking creates the file
+        state.files.entry(name.to_path_buf()).or_default();
+        Ok(Box::new(MemFileLock { name: name.to_path_buf() }))
+    }
+
+    fn unlock_file(&self, lock: Box<dyn FileLock>) -> Result<()> {
+        self.state.lock().unwrap().locks.remove(lock.name());
+        Ok(())
+    }
+
+    fn schedule(&self, function: Box<dyn FnOnce() + Send>) {
+        self.base.schedule(function)
+    }
+
+    fn start_thread(&self, function: Box<dyn FnOnce() + Send>) {
+        self.base.start_thread(function)
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.base.now_micros()
+    }
+
+    fn sleep_for_microseconds(&self, micros: u64) {
+        self.base.sleep_for_microseconds(micros)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use env::{read_file_to_vec, write_string_to_file};
+    use log::{Reader, Writer};
+    use util::coding::put_fixed64;
+
+    fn path(name: &str) -> &Path {
+        Path::new(name)
+    }
+
+    #[test]
+    fn basics() {
+        let env = MemEnv::new();
+        env.create_dir(path("/dir")).unwrap();
+
+        // Check that the directory is empty.
+        assert!(!env.file_exists(path("/dir/non_existent")));
+        assert!(env.get_file_size(path("/dir/non_existent")).is_err());
+        assert!(env.get_children(path("/dir")).unwrap().is_empty());
+
+        // Create a file.
+        let mut file = env.new_writable_file(path("/dir/f")).unwrap();
+        assert_eq!(env.get_file_size(path("/dir/f")).unwrap(), 0);
+        file.append(b"abc").unwrap();
+        drop(file);
+
+        // Check that the file exists.
+        assert!(env.file_exists(path("/dir/f")));
+        assert_eq!(env.get_file_size(path("/dir/f")).unwrap(), 3);
+        assert_eq!(env.get_children(path("/dir")).unwrap(), [PathBuf::from("f")]);
+
+        // Write to the file.
+        let mut file = env.new_writable_file(path("/dir/f")).unwrap();
+        file.append(b"abc").unwrap();
+        drop(file);
+
+        // Check for expected size.
+        assert_eq!(env.get_file_size(path("/dir/f")).unwrap(), 3);
+
+        // Check that renaming works.
+        assert!(env.rename_file(path("/dir/non_existent"), path("/dir/g")).is_err());
+        env.rename_file(path("/dir/f"), path("/dir/g")).unwrap();
+        assert!(!env.file_exists(path("/dir/f")));
+        assert!(env.file_exists(path("/dir/g")));
+        assert_eq!(env.get_file_size(path("/dir/g")).unwrap(), 3);
+
+        // Check that opening non-existent file fails.
+        assert!(env.new_sequential_file(path("/dir/non_existent")).is_err());
+        assert!(env.new_random_access_file(path("/dir/non_existent")).is_err());
+
+        // Check that deleting works.
+        assert!(env.remove_file(path("/dir/non_existent")).is_err());
+        env.remove_file(path("/dir/g")).unwrap();
+        assert!(!env.file_exists(path("/dir/g")));
+        assert!(env.get_children(path("/dir")).unwrap().is_empty());
+        env.remove_dir(path("/dir")).unwrap();
+    }
+
+    #[test]
+    fn read_write() {
+        let env = MemEnv::new();
+        let mut file = env.new_writable_file(path("/dir/f")).unwrap();
+        file.append(b"hello ").unwrap();
+        file.append(b"world").unwrap();
+        drop(file);
+
+        // Read sequentially.
+        let mut file = env.new_sequential_file(path("/dir/f")).unwrap();
+        let mut scratch = [0; 100];
+        assert_eq!(file.read(&mut scratch[..5]).unwrap(), 5);
+        assert_eq!(&scratch[..5], b"hello");
+        file.skip(1).unwrap();
+        assert_eq!(file.read(&mut scratch).unwrap(), 5);
+        assert_eq!(&scratch[..5], b"world");
+        assert_eq!(file.read(&mut scratch).unwrap(), 0);
+        // Too high skip: clamps to the end of the file.
+        file.skip(100).unwrap();
+        assert_eq!(file.read(&mut scratch[..5]).unwrap(), 0);
+
+        // Random reads.
+        let file = env.new_random_access_file(path("/dir/f")).unwrap();
+        assert_eq!(file.read(6, &mut scratch[..5]).unwrap(), 5);
+        assert_eq!(&scratch[..5], b"world");
+        assert_eq!(file.read(0, &mut scratch[..5]).unwrap(), 5);
+        assert_eq!(&scratch[..5], b"hello");
+        assert_eq!(file.read(10, &mut scratch[..100]).unwrap(), 1);
+        assert_eq!(&scratch[..1], b"d");
+
+        // Too high offset.
+        assert!(file.read(1000, &mut scratch[..5]).is_err());
+    }
+
+    #[test]
+    fn rename_over_existing() {
+        let env = MemEnv::new();
+        write_string_to_file(&env, b"old", path("/db/CURRENT"), false).unwrap();
+        write_string_to_file(&env, b"new contents", path("/db/tmp"), false).unwrap();
+        let reader = env.new_random_access_file(path("/db/CURRENT")).unwrap();
+
+        env.rename_file(path("/db/tmp"), path("/db/CURRENT")).unwrap();
+        assert!(!env.file_exists(path("/db/tmp")));
+        assert_eq!(read_file_to_vec(&env, path("/db/CURRENT")).unwrap(), b"new contents");
+
+        // Open files keep the replaced contents.
+        let mut scratch = [0; 10];
+        assert_eq!(reader.read(0, &mut scratch).unwrap(), 3);
+        assert_eq!(&scratch[..3], b"old");
+    }
+
+    #[test]
+    fn get_children() {
+        let env = MemEnv::new();
+        env.create_dir(path("/db")).unwrap();
+        env.create_dir(path("/db/lost")).unwrap();
+        for name in &["/db/a", "/db/b", "/db/lost/c", "/other/d"] {
+            write_string_to_file(&env, b"", path(name), false).unwrap();
+        }
+        let mut children = env.get_children(path("/db")).unwrap();
+        children.sort();
+        assert_eq!(children, [PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("lost")]);
+        assert_eq!(env.get_children(path("/db/lost")).unwrap(), [PathBuf::from("c")]);
+        assert!(env.get_children(path("/nowhere")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn locks() {
+        let env = MemEnv::new();
+        let lock = env.lock_file(path("/db/LOCK")).unwrap();
+        assert_eq!(lock.name(), path("/db/LOCK"));
+        assert!(env.file_exists(path("/db/LOCK")));
+
+        let err = env.lock_file(path("/db/LOCK")).err().unwrap();
+        assert!(err.is_io_error());
+        assert_eq!(err.message(), "lock /db/LOCK: already held by process");
+        let other = env.lock_file(path("/other/LOCK")).unwrap();
+
+        env.unlock_file(lock).unwrap();
+        let lock = env.lock_file(path("/db/LOCK")).unwrap();
+        env.unlock_file(lock).unwrap();
+        env.unlock_file(other).unwrap();
+    }
+
+    #[test]
+    fn file_contents_and_snapshot() {
+        let env = MemEnv::new();
+        let mut data = Vec::new();
+        put_fixed64(&mut data, 0x0102_0304_0506_0708);
+        write_string_to_file(&env, &data, path("/db/a"), true).unwrap();
+        write_string_to_file(&env, b"xyz", path("/db/b"), false).unwrap();
+
+        assert_eq!(
+            env.file_contents(path("/db/a")).unwrap(),
+            [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]
+        );
+        assert!(env.file_contents(path("/db/c")).is_none());
+
+        let snapshot = env.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[path("/db/a")], data);
+        assert_eq!(snapshot[path("/db/b")], b"xyz");
+
+        // A snapshot is a copy.
+        let mut file = env.new_appendable_file(path("/db/b")).unwrap();
+        file.append(b"!").unwrap();
+        assert_eq!(snapshot[path("/db/b")], b"xyz");
+        assert_eq!(env.file_contents(path("/db/b")).unwrap(), b"xyz!");
+    }
+
+    #[test]
+    fn log_round_trip() {
+        let env = MemEnv::new();
+        let name = path("/db/000001.log");
+        let records: Vec<Vec<u8>> = (0..200usize).map(|i| vec![i as u8; i * 311]).collect();
+
+        let mut writer = Writer::new(env.new_writable_file(name).unwrap());
+        for record in &records {
+            writer.add_record(record).unwrap();
+        }
+        drop(writer);
+
+        let mut reader = Reader::new(env.new_sequential_file(name).unwrap(), None, true, 0);
+        for record in &records {
+            assert_eq!(reader.read_record(), Some(&record[..]));
+        }
+        assert_eq!(reader.read_record(), None);
+    }
+}
@@ -0,0 +1,213 @@
+//! An `Env` is an interface used by the leveldb implementation to access
+//! operating system functionality like the filesystem etc. Callers
+//! may wish to provide a custom `Env` object when opening a database to
+//! get fine gain control; e.g., to rate limit file system operations.
+//!
+//! All `Env` implementations are safe for concurrent access from
+//! multiple threads without any external synchronization.
+
+use std::path::{Path, PathBuf};
+
+use error::Result;
+
+pub mod fault;
+pub mod mem;
+pub mod posix;
+
+pub use self::fault::FaultInjectionEnv;
+pub use self::mem::MemEnv;
+pub use self::posix::{default_env, PosixEnv};
+
+/// A file abstraction for reading sequentially through a file.
+pub trait SequentialFile: Send {
+    /// Reads up to `buf.len()` bytes from the file, returning the number of
+    /// bytes read. Returns 0 only at the end of the file.
+    ///
+    /// REQUIRES: External synchronization
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Skips `n` bytes from the file. This is guaranteed to be no
+    /// slower that reading the same data, but may be faster.
+    ///
+    /// If end of file is reached, skipping will stop at the end of the
+    /// file, and `skip` will return `Ok`.
+    ///
+    /// REQUIRES: External synchronization
+    fn skip(&mut self, n: u64) -> Result<()>;
+}
+
+/// A file abstraction for randomly reading the contents of a file.
+pub trait RandomAccessFile: Send + Sync {
+    /// Reads up to `buf.len()` bytes from the file starting at `offset`,
+    /// returning the number of bytes read.
+    ///
+    /// Safe for concurrent use by multiple threads.
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize>;
+}
+
+/// A file abstraction for sequential writing. The implementation
+/// must provide buffering since callers may append small fragments
+/// at a time to the file.
+pub trait WritableFile: Send {
+    fn append(&mut self, data: &[u8]) -> Result<()>;
+    fn close(&mut self) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+    fn sync(&mut self) -> Result<()>;
+}
+
+/// Identifies a locked file.
+pub trait FileLock: Send {
+    fn name(&self) -> &Path;
+}
+
+pub trait Env: Send + Sync {
+    /// Creates an object that sequentially reads the file with the specified
+    /// name.
+    ///
+    /// Fails with `NotFound` if the file does not exist.
+    fn new_sequential_file(&self, name: &Path) -> Result<Box<dyn SequentialFile>>;
+
+    /// Creates an object supporting random-access reads from the file with
+    /// the specified name.
+    ///
+    /// Fails with `NotFound` if the file does not exist.
+    fn new_random_access_file(&self, name: &Path) -> Result<Box<dyn RandomAccessFile>>;
+
+    /// Creates an object that writes to a new file with the specified
+    /// name. Deletes any existing file with the same name and creates a
+    /// new file.
+    fn new_writable_file(&self, name: &Path) -> Result<Box<dyn WritableFile>>;
+
+    /// Creates an object that either appends to an existing file, or
+    /// writes to a new file (if the file does not exist to begin with).
+    fn new_appendable_file(&self, name: &Path) -> Result<Box<dyn WritableFile>>;
+
+    /// Returns true iff the named file exists.
+    fn file_exists(&self, name: &Path) -> bool;
+
+    /// Returns the names of the children of the specified directory.
+    /// The names are relative to `dir`.
+    fn get_children(&self, dir: &Path) -> Result<Vec<PathBuf>>;
+
+    /// Deletes the named file.
+    fn remove_file(&self, name: &Path) -> Result<()>;
+
+    /// Creates the specified directory.
+    fn create_dir(&self, name: &Path) -> Result<()>;
+
+    /// Deletes the specified directory.
+    fn remove_dir(&self, name: &Path) -> Result<()>;
+
+    /// Returns the size of the named file.
+    fn get_file_size(&self, name: &Path) -> Result<u64>;
+
+    /// Renames file `src` to `target`.
+    fn rename_file(&self, src: &Path, target: &Path) -> Result<()>;
+
+    /// Locks the specified file. Used to prevent concurrent access to
+    /// the same db by multiple processes. On failure, returns an error.
+    ///
+    /// On success, returns a lock that represents the acquired lock. The
+    /// caller should call `unlock_file(lock)` to release the lock. If the
+    /// process exits, the lock will be automatically released.
+    ///
+    /// If somebody else already holds the lock, finishes immediately
+    /// with a failure. I.e., this call does not wait for existing locks
+    /// to go away.
+    ///
+    /// May create the named file if it does not already exist.
+    fn lock_file(&self, name: &Path) -> Result<Box<dyn FileLock>>;
+
+    /// Releases the lock acquired by a previous successful call to
+    /// `lock_file`.
+    fn unlock_file(&self, lock: Box<dyn FileLock>) -> Result<()>;
+
+    /// Arranges to run `function` once in a background thread.
+    ///
+    /// `function` may run in an unspecified thread. Multiple functions
+    /// added to the same `Env` may run concurrently in different threads.
+    /// I.e., the caller may not assume that background work items are
+    /// serialized.
+    fn schedule(&self, function: Box<dyn FnOnce() + Send>);
+
+    /// Starts a new thread, invoking `function` within the new thread.
+    /// When `function` returns, the thread will be destroyed.
+    fn start_thread(&self, function: Box<dyn FnOnce() + Send>);
+
+    /// Returns the number of micro-seconds since some fixed point in time.
+    /// Only useful for computing deltas of time.
+    fn now_micros(&self) -> u64;
+
+    /// Sleeps/delays the thread for the prescribed number of micro-seconds.
+    fn sleep_for_microseconds(&self, micros: u64);
+}
+
+impl<F: SequentialFile + ?Sized> SequentialFile for Box<F> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> {
+        (**self).skip(n)
+    }
+}
+
+impl<F: RandomAccessFile + ?Sized> RandomAccessFile for Box<F> {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(offset, buf)
+    }
+}
+
+impl<F: WritableFile + ?Sized> WritableFile for Box<F> {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        (**self).append(data)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        (**self).close()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        (**self).sync()
+    }
+}
+
+/// Writes `data` to the named file, optionally syncing it before closing.
+pub fn write_string_to_file(env: &dyn Env, data: &[u8], name: &Path, should_sync: bool) -> Result<()> {
+    let mut file = env.new_writable_file(name)?;
+    let result = file
+        .append(data)
+        .and_then(|_| if should_sync { file.sync() } else { Ok(()) })
+        .and_then(|_| file.close());
+    if result.is_err() {
+        let _ = env.remove_file(name);
+    }
+    result
+}
+
+/// Returns true if `name` is a MANIFEST file, whose directory has to be
+/// synced along with it so that newly created files become durable.
+pub(crate) fn is_manifest(name: &Path) -> bool {
+    name.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with("MANIFEST"))
+}
+
+/// Reads the whole named file.
+pub fn read_file_to_vec(env: &dyn Env, name: &Path) -> Result<Vec<u8>> {
+    const BUFFER_SIZE: usize = 8192;
+    let mut file = env.new_sequential_file(name)?;
+    let mut data = Vec::new();
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(data);
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use env::{is_manifest, Env, FileLock, RandomAccessFile, SequentialFile, WritableFile};
+use error::{Error, Result};
+
+const WRITABLE_FILE_BUFFER_SIZE: usize = 65536;
+
+fn posix_error(context: &Path, e: &io::Error) -> Error {
+    let msg = format!("{}: {}", context.display(), e);
+    if e.kind() == io::ErrorKind::NotFound {
+        Error::NotFound(msg)
+    } else {
+        Error::IOError(msg)
+    }
+}
+
+struct PosixSequentialFile {
+    file: File,
+    filename: PathBuf,
+}
+
+impl SequentialFile for PosixSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.file.read(buf) {
+                Ok(n) => return Ok(n),
+                // Retry
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(posix_error(&self.filename, &e)),
+            }
+        }
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> {
+        let n = i64::try_from(n).map_err(|_| {
+            Error::io_error(format!("{}: cannot skip {} bytes", self.filename.display(), n))
+        })?;
+        self.file
+            .seek(SeekFrom::Current(n))
+            .map(|_| ())
+            .map_err(|e| posix_error(&self.filename, &e))
+    }
+}
+
+struct PosixRandomAccessFile {
+    file: File,
+    filename: PathBuf,
+}
+
+impl RandomAccessFile for PosixRandomAccessFile {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        #[cfg(unix)]
+        let result = {
+            use std::os::unix::fs::FileExt;
+            self.file.read_at(buf, offset)
+        };
+        #[cfg(windows)]
+        let result = {
+            use std::os::windows::fs::FileExt;
+            self.file.seek_read(buf, offset)
+        };
+        result.map_err(|e| posix_error(&self.filename, &e))
+    }
+}
+
+struct PosixWritableFile {
+    // None once the file has been closed
+    file: Option<File>,
+    // buf holds data that has not been written to the file yet
+    buf: Vec<u8>,
+    filename: PathBuf,
+    // True if the file's name starts with MANIFEST.
+    is_manifest: bool,
+}
+
+impl PosixWritableFile {
+    fn new(file: File, filename: &Path) -> PosixWritableFile {
+        PosixWritableFile {
+            file: Some(file),
+            buf: Vec::with_capacity(WRITABLE_FILE_BUFFER_SIZE),
+            filename: filename.to_path_buf(),
+            is_manifest: is_manifest(filename),
+        }
+    }
+
+    fn write_unbuffered(&mut self, data: &[u8]) -> Result<()> {
+        write_to(&mut self.file, &self.filename, data)
+    }
+
+    fn flush_buffer(&mut self) -> Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let result = write_to(&mut self.file, &self.filename, &self.buf);
+        self.buf.clear();
+        result
+    }
+
+    fn sync_dir_if_manifest(&self) -> Result<()> {
+        if !self.is_manifest {
+            return Ok(());
+        }
+        // The new manifest refers to files that were just created; make sure
+        // their directory entries are durable too.
+        let dir = match self.filename.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)
+            .and_then(|d| d.sync_all())
+            .map_err(|e| posix_error(dir, &e))
+    }
+}
+
+impl WritableFile for PosixWritableFile {
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        opened(&mut self.file, &self.filename)?;
+
+        // Fit as much as possible into buffer.
+        let copy_size = data.len().min(WRITABLE_FILE_BUFFER_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..copy_size]);
+        let rest = &data[copy_size..];
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        // Can't fit in buffer, so need to do at least one write.
+        self.flush_buffer()?;
+
+        // Small writes go to buffer, large writes are written directly.
+        if rest.len() < WRITABLE_FILE_BUFFER_SIZE {
+            self.buf.extend_from_slice(rest);
+            return Ok(());
+        }
+        self.write_unbuffered(rest)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+        let result = self.flush_buffer();
+        self.file = None;
+        result
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_buffer()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        // Ensure new files referred to by the manifest are in the filesystem.
+        //
+        // This needs to happen before the manifest file is flushed to disk, to
+        // avoid crashing in a state where the manifest refers to files that are
+        // not yet on disk.
+        self.sync_dir_if_manifest()?;
+        self.flush_buffer()?;
+        opened(&mut self.file, &self.filename)?
+            .sync_data()
+            .map_err(|e| posix_error(&self.filename, &e))
+    }
+}
+
+// Takes the fields separately so callers can keep borrowing the rest of the
+// `PosixWritableFile`.
+fn opened<'a>(file: &'a mut Option<File>, filename: &Path) -> Result<&'a mut File> {
+    file.as_mut()
+        .ok_or_else(|| Error::io_error(format!("{}: file is closed", filename.display())))
+}
+
+fn write_to(file: &mut Option<File>, filename: &Path, data: &[u8]) -> Result<()> {
+    opened(file, filename)?
+        .write_all(data)
+        .map_err(|e| posix_error(filename, &e))
+}
+
+impl Drop for PosixWritableFile {
+    fn drop(&mut self) {
+        // Ignoring any potential errors
+        let _ = self.close();
+    }
+}
+
+struct PosixFileLock {
+    // dropping the file releases the lock
+    _file: File,
+    filename: PathBuf,
+}
+
+impl FileLock for PosixFileLock {
+    fn name(&self) -> &Path {
+        &self.filename
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct BackgroundQueue {
+    state: Mutex<(bool, VecDeque<Job>)>,
+    work_available: Condvar,
+}
+
+impl BackgroundQueue {
+    fn run(&self) {
+        loop {
+            let job = {
+                let mut state = self.state.lock().unwrap();
+                // Wait until there is work to be done.
+                while state.1.is_empty() {
+                    state = self.work_available.wait(state).unwrap();
+                }
+                state.1.pop_front().unwrap()
+            };
+            job();
+        }
+    }
+}
+
+/// The default `Env`, backed by the local file system and native threads.
+#[derive(Default)]
+pub struct PosixEnv {
+    // Files locked through this env, so that locking one twice from the same
+    // process fails regardless of the platform's file locking semantics.
+    locks: Mutex<HashSet<PathBuf>>,
+    background: Arc<BackgroundQueue>,
+}
+
+impl PosixEnv {
+    pub fn new() -> PosixEnv {
+        PosixEnv::default()
+    }
+}
+
+impl Env for PosixEnv {
+    fn new_sequential_file(&self, name: &Path) -> Result<Box<dyn SequentialFile>> {
+        let file = File::open(name).map_err(|e| posix_error(name, &e))?;
+        Ok(Box::new(PosixSequentialFile { file, filename: name.to_path_buf() }))
+    }
+
+    fn new_random_access_file(&self, name: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        let file = File::open(name).map_err(|e| posix_error(name, &e))?;
+        Ok(Box::new(PosixRandomAccessFile { file, filename: name.to_path_buf() }))
+    }
+
+    fn new_writable_file(&self, name: &Path) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(name)
+            .map_err(|e| posix_error(name, &e))?;
+        Ok(Box::new(PosixWritableFile::new(file, name)))
+    }
+
+    fn new_appendable_file(&self, name: &Path) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(name)
+            .map_err(|e| posix_error(name, &e))?;
+        Ok(Box::new(PosixWritableFile::new(file, name)))
+    }
+
+    fn file_exists(&self, name: &Path) -> bool {
+        name.exists()
+    }
+
+    fn get_children(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut result = Vec::new();
+        for entry in fs::read_dir(dir).map_err(|e| posix_error(dir, &e))? {
+            let entry = entry.map_err(|e| posix_error(dir, &e))?;
+            result.push(PathBuf::from(entry.file_name()));
+        }
+        Ok(result)
+    }
+
+    fn remove_file(&self, name: &Path) -> Result<()> {
+        fs::remove_file(name).map_err(|e| posix_error(name, &e))
+    }
+
+    fn create_dir(&self, name: &Path) -> Result<()> {
+        fs::create_dir(name).map_err(|e| posix_error(name, &e))
+    }
+
+    fn remove_dir(&self, name: &Path) -> Result<()> {
+        fs::remove_dir(name).map_err(|e| posix_error(name, &e))
+    }
+
+    fn get_file_size(&self, name: &Path) -> Result<u64> {
+        fs::metadata(name)
+            .map(|m| m.len())
+            .map_err(|e| posix_error(name, &e))
+    }
+
+    fn rename_file(&self, src: &Path, target: &Path) -> Result<()> {
+        fs::rename(src, target).map_err(|e| posix_error(src, &e))
+    }
+
+    fn lock_file(&self, name: &Path) -> Result<Box<dyn FileLock>> {
+        let mut locks = self.locks.lock().unwrap();
+        if locks.contains(name) {
+            return Err(Error::io_error(format!(
+                "lock {}: already held by process",
+                name.display()
+            )));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(name)
+            .map_err(|e| posix_error(name, &e))?;
+        if let Err(e) = file.try_lock() {
+            return Err(Error::io_error(format!("lock {}: {}", name.display(), e)));
+        }
+        locks.insert(name.to_path_buf());
+        Ok(Box::new(PosixFileLock { _file: file, filename: name.to_path_buf() }))
+    }
+
+    fn unlock_file(&self, lock: Box<dyn FileLock>) -> Result<()> {
+        self.locks.lock().unwrap().remove(lock.name());
+        // dropping the lock closes the file, which releases the lock
+        Ok(())
+    }
+
+    fn schedule(&self, function: Box<dyn FnOnce() + Send>) {
+        let mut state = self.background.state.lock().unwrap();
+
+        // Start the background thread, if we haven't done so already.
+        if !state.0 {
+            state.0 = true;
+            let background = self.background.clone();
+            thread::spawn(move || background.run());
+        }
+
+        state.1.push_back(function);
+        self.background.work_available.notify_one();
+    }
+
+    fn start_thread(&self, function: Box<dyn FnOnce() + Send>) {
+        thread::spawn(function);
+    }
+
+    fn now_micros(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_secs() * 1_000_000 + u64::from(now.subsec_micros())
+    }
+
+    fn sleep_for_microseconds(&self, micros: u64) {
+        thread::sleep(Duration::from_micros(micros));
+    }
+}
+
+/// Returns the process-wide `PosixEnv`.
+pub fn default_env() -> Arc<dyn Env> {
+    static DEFAULT: OnceLock<Arc<PosixEnv>> = OnceLock::new();
+    DEFAULT.get_or_init(|| Arc::new(PosixEnv::new())).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env as std_env;
+    use std::process;
+
+    use super::*;
+    use env::read_file_to_vec;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std_env::temp_dir().join(format!("leveldb-posix-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn write_sync_read() {
+        let env = PosixEnv::new();
+        let dir = test_dir("write");
+        let name = dir.join("MANIFEST-000001");
+        let mut file = env.new_writable_file(&name).unwrap();
+        file.append(b"hello ").unwrap();
+        // larger than the buffer, so it is written directly
+        file.append(&vec![b'x'; WRITABLE_FILE_BUFFER_SIZE + 1]).unwrap();
+        file.sync().unwrap();
+        file.close().unwrap();
+        assert!(file.append(b"more").unwrap_err().is_io_error());
+        // the rejected data was not buffered either
+        assert!(file.flush().is_ok());
+
+        let data = read_file_to_vec(&env, &name).unwrap();
+        assert_eq!(data.len(), 6 + WRITABLE_FILE_BUFFER_SIZE + 1);
+        assert_eq!(&data[..6], b"hello ");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skip() {
+        let env = PosixEnv::new();
+        let dir = test_dir("skip");
+        let name = dir.join("f");
+        fs::write(&name, b"0123456789").unwrap();
+
+        let mut file = env.new_sequential_file(&name).unwrap();
+        file.skip(4).unwrap();
+        let mut buf = [0; 3];
+        assert_eq!(file.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"456");
+        assert!(file.skip(u64::MAX).unwrap_err().is_io_error());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+/// The crate-wide error type, modelled on LevelDB's `Status`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    NotFound(String),
+    Corruption(String),
+    NotSupported(String),
+    InvalidArgument(String),
+    IOError(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl Error {
+    pub fn not_found<M: Into<String>>(msg: M) -> Error {
+        Error::NotFound(msg.into())
+    }
+
+    pub fn corruption<M: Into<String>>(msg: M) -> Error {
+        Error::Corruption(msg.into())
+    }
+
+    pub fn not_supported<M: Into<String>>(msg: M) -> Error {
+        Error::NotSupported(msg.into())
+    }
+
+    pub fn invalid_argument<M: Into<String>>(msg: M) -> Error {
+        Error::InvalidArgument(msg.into())
+    }
+
+    pub fn io_error<M: Into<String>>(msg: M) -> Error {
+        Error::IOError(msg.into())
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(*self, Error::NotFound(_))
+    }
+
+    pub fn is_corruption(&self) -> bool {
+        matches!(*self, Error::Corruption(_))
+    }
+
+    pub fn is_not_supported(&self) -> bool {
+        matches!(*self, Error::NotSupported(_))
+    }
+
+    pub fn is_invalid_argument(&self) -> bool {
+        matches!(*self, Error::InvalidArgument(_))
+    }
+
+    pub fn is_io_error(&self) -> bool {
+        matches!(*self, Error::IOError(_))
+    }
+
+    pub fn message(&self) -> &str {
+        match *self {
+            Error::NotFound(ref msg)
+            | Error::Corruption(ref msg)
+            | Error::NotSupported(ref msg)
+            | Error::InvalidArgument(ref msg)
+            | Error::IOError(ref msg) => msg,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match *self {
+            Error::NotFound(_) => "NotFound",
+            Error::Corruption(_) => "Corruption",
+            Error::NotSupported(_) => "Not implemented",
+            Error::InvalidArgument(_) => "Invalid argument",
+            Error::IOError(_) => "IO error",
+        };
+        write!(f, "{}: {}", kind, self.message())
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IOError(e.to_string())
+    }
+}
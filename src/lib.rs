@@ -1,6 +1,9 @@
-#![feature(const_size_of)]
-#![feature(offset_to)]
-
 extern crate byteorder;
 
+pub mod db;
+pub mod env;
+pub mod error;
+pub mod log;
 pub mod util;
+
+pub use error::{Error, Result};
@@ -0,0 +1,41 @@
+//! Log format information shared by reader and writer.
+//!
+//! The log file is divided into 32 KiB blocks. Each physical record starts
+//! with a 7 byte header:
+//!
+//! ```text
+//!    checksum: uint32  // masked crc32c of type and data, little-endian
+//!    length:   uint16  // little-endian
+//!    type:     uint8   // one of FULL, FIRST, MIDDLE, LAST
+//!    data:     uint8[length]
+//! ```
+//!
+//! A record never starts within the last six bytes of a block (since it
+//! won't fit); any leftover bytes there form the trailer, which consists
+//! entirely of zero bytes and is skipped by readers.
+
+pub mod reader;
+pub mod writer;
+
+pub use self::reader::{Reader, Reporter};
+pub use self::writer::Writer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RecordType {
+    // Zero is reserved for preallocated files
+    Zero = 0,
+    Full = 1,
+
+    // For fragments
+    First = 2,
+    Middle = 3,
+    Last = 4,
+}
+
+pub const MAX_RECORD_TYPE: u8 = RecordType::Last as u8;
+
+pub const BLOCK_SIZE: usize = 32768;
+
+/// Header is checksum (4 bytes), length (2 bytes), type (1 byte).
+pub const HEADER_SIZE: usize = 4 + 2 + 1;
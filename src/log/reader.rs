@@ -0,0 +1,1065 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use env::SequentialFile;
+use error::{Error, Result};
+use log::{RecordType, BLOCK_SIZE, HEADER_SIZE};
+use util::crc32c;
+
+/// Interface for reporting errors.
+pub trait Reporter {
+    /// Some corruption was detected. `bytes` is the approximate number
+    /// of bytes dropped due to the corruption.
+    fn corruption(&mut self, bytes: usize, reason: &Error);
+}
+
+// What read_physical_record() found: a record type byte with the payload's
+// position in the backing store, or one of two special outcomes.
+enum Physical {
+    Record(u8, usize, usize),
+    Eof,
+    // Returned whenever we find an invalid physical record.
+    // Currently there are three situations in which this happens:
+    // * The record has an invalid CRC (read_physical_record reports a drop)
+    // * The record is a 0-length record (No drop is reported)
+    // * The record is below the constructor's initial_offset (No drop is reported)
+    BadRecord,
+}
+
+/// Reads records written by `log::Writer`, reassembling fragments and
+/// skipping over damaged regions.
+pub struct Reader<R> {
+    file: R,
+    reporter: Option<Box<dyn Reporter>>,
+    checksum: bool,
+    backing_store: Vec<u8>,
+    // the unread part of the current block is backing_store[buffer_start..buffer_end]
+    buffer_start: usize,
+    buffer_end: usize,
+    // Last read() indicated EOF by returning < BLOCK_SIZE
+    eof: bool,
+    scratch: Vec<u8>,
+
+    // Offset of the last record returned by read_record.
+    last_record_offset: u64,
+    // Offset of the first location past the end of buffer.
+    end_of_buffer_offset: u64,
+
+    // Offset at which to start looking for the first record to return
+    initial_offset: u64,
+
+    // True if we are resynchronizing after a seek (initial_offset > 0). In
+    // particular, a run of Middle and Last records can be silently
+    // skipped in this mode
+    resyncing: bool,
+}
+
+impl<R: SequentialFile> Reader<R> {
+    /// Creates a reader that will return log records from `file`.
+    ///
+    /// If `reporter` is set, it is notified whenever some data is dropped
+    /// due to a detected corruption.
+    ///
+    /// If `checksum` is true, verify checksums if available.
+    ///
+    /// The reader will start reading at the first record located at
+    /// physical position >= `initial_offset` within the file.
+    pub fn new(
+        file: R,
+        reporter: Option<Box<dyn Reporter>>,
+        checksum: bool,
+        initial_offset: u64,
+    ) -> Reader<R> {
+        Reader {
+            file,
+            reporter,
+            checksum,
+            backing_store: vec![0; BLOCK_SIZE],
+            buffer_start: 0,
+            buffer_end: 0,
+            eof: false,
+            scratch: Vec::new(),
+            last_record_offset: 0,
+            end_of_buffer_offset: 0,
+            initial_offset,
+            resyncing: initial_offset > 0,
+        }
+    }
+
+    /// Reads the next record. Returns `None` at end of input. The returned
+    /// slice is only valid until the next call to `read_record`.
+    pub fn read_record(&mut self) -> Option<&[u8]> {
+        if self.last_record_offset < self.initial_offset && !self.skip_to_initial_block() {
+            return None;
+        }
+
+        self.scratch.clear();
+        let mut in_fragmented_record = false;
+        // Record offset of the logical record that we're reading
+        // 0 is a dummy value to make compilers happy
+        let mut prospective_record_offset = 0;
+
+        loop {
+            let physical = self.read_physical_record();
+
+            // read_physical_record may have only had an empty trailer
+            // remaining in its internal buffer. Calculate the offset of the
+            // next physical record now that it has returned, properly
+            // accounting for its header size.
+            let fragment_len = match physical {
+                Physical::Record(_, start, end) => end - start,
+                _ => 0,
+            };
+            let physical_record_offset = self
+                .end_of_buffer_offset
+                .wrapping_sub(self.buffer_len() as u64)
+                .wrapping_sub(HEADER_SIZE as u64)
+                .wrapping_sub(fragment_len as u64);
+
+            if self.resyncing {
+                match physical {
+                    Physical::Record(t, _, _) if t == RecordType::Middle as u8 => continue,
+                    Physical::Record(t, _, _) if t == RecordType::Last as u8 => {
+                        self.resyncing = false;
+                        continue;
+                    }
+                    _ => self.resyncing = false,
+                }
+            }
+
+            match physical {
+                Physical::Record(t, start, end) if t == RecordType::Full as u8 => {
+                    if in_fragmented_record {
+                        // Handle bug in earlier versions of log::Writer where
+                        // it could emit an empty First record at the tail end
+                        // of a block followed by a Full or First record at
+                        // the beginning of the next block.
+                        if !self.scratch.is_empty() {
+                            let n = self.scratch.len();
+                            self.report_corruption(n, "partial record without end(1)");
+                        }
+                    }
+                    prospective_record_offset = physical_record_offset;
+                    self.scratch.clear();
+                    self.last_record_offset = prospective_record_offset;
+                    return Some(&self.backing_store[start..end]);
+                }
+
+                Physical::Record(t, start, end) if t == RecordType::First as u8 => {
+                    if in_fragmented_record {
+                        // Handle bug in earlier versions of log::Writer where
+                        // it could emit an empty First record at the tail end
+                        // of a block followed by a Full or First record at
+                        // the beginning of the next block.
+                        if !self.scratch.is_empty() {
+                            let n = self.scratch.len();
+                            self.report_corruption(n, "partial record without end(2)");
+                        }
+                    }
+                    prospective_record_offset = physical_record_offset;
+                    self.scratch.clear();
+                    self.scratch.extend_from_slice(&self.backing_store[start..end]);
+                    in_fragmented_record = true;
+                }
+
+                Physical::Record(t, start, end) if t == RecordType::Middle as u8 => {
+                    if !in_fragmented_record {
+                        self.report_corruption(end - start, "missing start of fragmented record(1)");
+                    } else {
+                        self.scratch.extend_from_slice(&self.backing_store[start..end]);
+                    }
+                }
+
+                Physical::Record(t, start, end) if t == RecordType::Last as u8 => {
+                    if !in_fragmented_record {
+                        self.report_corruption(end - start, "missing start of fragmented record(2)");
+                    } else {
+                        self.scratch.extend_from_slice(&self.backing_store[start..end]);
+                        self.last_record_offset = prospective_record_offset;
+                        return Some(&self.scratch);
+                    }
+                }
+
+                Physical::Eof => {
+                    if in_fragmented_record {
+                        // This can be caused by the writer dying immediately
+                        // after writing a physical record but before
+                        // completing the next; don't treat it as a
+                        // corruption, just ignore the entire logical record.
+                        self.scratch.clear();
+                    }
+                    return None;
+                }
+
+                Physical::BadRecord => {
+                    if in_fragmented_record {
+                        let n = self.scratch.len();
+                        self.report_corruption(n, "error in middle of record");
+                        in_fragmented_record = false;
+                        self.scratch.clear();
+                    }
+                }
+
+                Physical::Record(t, start, end) => {
+                    let dropped = end - start
+                        + if in_fragmented_record { self.scratch.len() } else { 0 };
+                    self.report_corruption(dropped, &format!("unknown record type {}", t));
+                    in_fragmented_record = false;
+                    self.scratch.clear();
+                }
+            }
+        }
+    }
+
+    /// Returns the physical offset of the last record returned by
+    /// `read_record`.
+    ///
+    /// Undefined before the first call to `read_record`.
+    pub fn last_record_offset(&self) -> u64 {
+        self.last_record_offset
+    }
+
+    fn buffer_len(&self) -> usize {
+        self.buffer_end - self.buffer_start
+    }
+
+    fn clear_buffer(&mut self) {
+        self.buffer_start = 0;
+        self.buffer_end = 0;
+    }
+
+    // Skips all blocks that are completely before "initial_offset".
+    //
+    // Returns true on success. Handles reporting.
+    fn skip_to_initial_block(&mut self) -> bool {
+        let offset_in_block = (self.initial_offset % BLOCK_SIZE as u64) as usize;
+        let mut block_start_location = self.initial_offset - offset_in_block as u64;
+
+        // Don't search a block if we'd be in the trailer
+        if offset_in_block > BLOCK_SIZE - 6 {
+            block_start_location += BLOCK_SIZE as u64;
+        }
+
+        self.end_of_buffer_offset = block_start_location;
+
+        // Skip to start of first block that can contain the initial record
+        if block_start_location > 0 {
+            if let Err(e) = self.file.skip(block_start_location) {
+                self.report_drop(block_start_location as usize, &e);
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn read_physical_record(&mut self) -> Physical {
+        loop {
+            if self.buffer_len() < HEADER_SIZE {
+                if !self.eof {
+                    // Last read was a full read, so this is a trailer to skip
+                    self.clear_buffer();
+                    match read_full(&mut self.file, &mut self.backing_store) {
+                        Ok(n) => {
+                            self.buffer_end = n;
+                            self.end_of_buffer_offset += n as u64;
+                            if n < BLOCK_SIZE {
+                                self.eof = true;
+                            }
+                        }
+                        Err(e) => {
+                            self.report_drop(BLOCK_SIZE, &e);
+                            self.eof = true;
+                            return Physical::Eof;
+                        }
+                    }
+                    continue;
+                } else {
+                    // Note that if buffer is non-empty, we have a truncated
+                    // header at the end of the file, which can be caused by
+                    // the writer crashing in the middle of writing the
+                    // header. Instead of considering this an error, just
+                    // report EOF.
+                    self.clear_buffer();
+                    return Physical::Eof;
+                }
+            }
+
+            // Parse the header
+            let header = &self.backing_store[self.buffer_start..self.buffer_end];
+            let length = header[4] as usize | (header[5] as usize) << 8;
+            let record_type = header[6];
+            if HEADER_SIZE + length > header.len() {
+                let drop_size = header.len();
+                self.clear_buffer();
+                if !self.eof {
+                    self.report_corruption(drop_size, "bad record length");
+                    return Physical::BadRecord;
+                }
+                // If the end of the file has been reached without reading
+                // `length` bytes of payload, assume the writer died in the
+                // middle of writing the record. Don't report a corruption.
+                return Physical::Eof;
+            }
+
+            if record_type == RecordType::Zero as u8 && length == 0 {
+                // Skip zero length record without reporting any drops since
+                // such records are produced by the mmap based writing code
+                // in env_posix.cc that preallocates file regions.
+                self.clear_buffer();
+                return Physical::BadRecord;
+            }
+
+            // Check crc
+            if self.checksum {
+                let expected_crc = crc32c::unmask(LittleEndian::read_u32(header));
+                let actual_crc = crc32c::value(&header[6..HEADER_SIZE + length]);
+                if actual_crc != expected_crc {
+                    // Drop the rest of the buffer since "length" itself may
+                    // have been corrupted and if we trust it, we could find
+                    // some fragment of a real log record that just happens
+                    // to look like a valid log record.
+                    let drop_size = header.len();
+                    self.clear_buffer();
+                    self.report_corruption(drop_size, "checksum mismatch");
+                    return Physical::BadRecord;
+                }
+            }
+
+            let start = self.buffer_start + HEADER_SIZE;
+            let end = start + length;
+            self.buffer_start = end;
+
+            // Skip physical record that started before initial_offset
+            let record_offset = self.end_of_buffer_offset
+                - (self.buffer_len() + HEADER_SIZE + length) as u64;
+            if record_offset < self.initial_offset {
+                return Physical::BadRecord;
+            }
+
+            return Physical::Record(record_type, start, end);
+        }
+    }
+
+    // Reports dropped bytes to the reporter.
+    fn report_corruption(&mut self, bytes: usize, reason: &str) {
+        self.report_drop(bytes, &Error::corruption(reason));
+    }
+
+    fn report_drop(&mut self, bytes: usize, reason: &Error) {
+        let offset = self
+            .end_of_buffer_offset
+            .wrapping_sub(self.buffer_len() as u64)
+            .wrapping_sub(bytes as u64);
+        if offset >= self.initial_offset {
+            if let Some(ref mut reporter) = self.reporter {
+                reporter.corruption(bytes, reason);
+            }
+        }
+    }
+}
+
+// Reads until `buf` is full or the end of the file is reached.
+fn read_full<R: SequentialFile>(file: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match file.read(&mut buf[n..])? {
+            0 => break,
+            read => n += read,
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use env::WritableFile;
+    use log::Writer;
+    use util::random::Random;
+
+    // Construct a string of the specified length made out of the supplied
+    // partial string.
+    fn big_string(partial: &str, n: usize) -> String {
+        let mut result = String::new();
+        while result.len() < n {
+            result.push_str(partial);
+        }
+        result.truncate(n);
+        result
+    }
+
+    // Construct a string from a number
+    fn number_string(n: usize) -> String {
+        format!("{}.", n)
+    }
+
+    // Return a skewed potentially long string
+    fn random_skewed_string(i: usize, rnd: &mut Random) -> String {
+        big_string(&number_string(i), rnd.skewed(17) as usize)
+    }
+
+    struct StringDest {
+        contents: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl WritableFile for StringDest {
+        fn append(&mut self, data: &[u8]) -> Result<()> {
+            self.contents.lock().unwrap().extend_from_slice(data);
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn sync(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    struct StringSource {
+        contents: Vec<u8>,
+        pos: usize,
+        force_error: bool,
+    }
+
+    impl SequentialFile for StringSource {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.force_error {
+                self.force_error = false;
+                return Err(Error::corruption("read error"));
+            }
+            let n = buf.len().min(self.contents.len() - self.pos);
+            buf[..n].copy_from_slice(&self.contents[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+
+        fn skip(&mut self, n: u64) -> Result<()> {
+            if n > (self.contents.len() - self.pos) as u64 {
+                self.pos = self.contents.len();
+                return Err(Error::not_found("in-memory file skipped past end"));
+            }
+            self.pos += n as usize;
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Report {
+        dropped_bytes: usize,
+        message: String,
+    }
+
+    struct ReportCollector(Rc<RefCell<Report>>);
+
+    impl Reporter for ReportCollector {
+        fn corruption(&mut self, bytes: usize, reason: &Error) {
+            let mut report = self.0.borrow_mut();
+            report.dropped_bytes += bytes;
+            report.message.push_str(&reason.to_string());
+        }
+    }
+
+    // Record metadata for testing initial offset functionality
+    const INITIAL_OFFSET_RECORD_SIZES: [usize; 6] = [
+        10000, // Two sizable records in first block
+        10000,
+        2 * BLOCK_SIZE - 1000, // Span three blocks
+        1,
+        13716,                     // Consume all but two bytes of block 3.
+        BLOCK_SIZE - HEADER_SIZE, // Consume the entirety of block 4.
+    ];
+
+    const INITIAL_OFFSET_LAST_RECORD_OFFSETS: [u64; 6] = [
+        0,
+        (HEADER_SIZE + 10000) as u64,
+        (2 * (HEADER_SIZE + 10000)) as u64,
+        (2 * (HEADER_SIZE + 10000) + (2 * BLOCK_SIZE - 1000) + 3 * HEADER_SIZE) as u64,
+        (2 * (HEADER_SIZE + 10000) + (2 * BLOCK_SIZE - 1000) + 3 * HEADER_SIZE + HEADER_SIZE + 1)
+            as u64,
+        (3 * BLOCK_SIZE) as u64,
+    ];
+
+    struct LogTest {
+        dest: Arc<Mutex<Vec<u8>>>,
+        writer: Writer<StringDest>,
+        reader: Option<Reader<StringSource>>,
+        report: Rc<RefCell<Report>>,
+        force_error: bool,
+    }
+
+    impl LogTest {
+        fn new() -> LogTest {
+            let dest = Arc::new(Mutex::new(Vec::new()));
+            LogTest {
+                writer: Writer::new(StringDest { contents: dest.clone() }),
+                dest,
+                reader: None,
+                report: Rc::new(RefCell::new(Report::default())),
+                force_error: false,
+            }
+        }
+
+        fn reopen_for_append(&mut self) {
+            let length = self.written_bytes() as u64;
+            self.writer = Writer::with_dest_length(StringDest { contents: self.dest.clone() }, length);
+        }
+
+        fn write(&mut self, msg: &str) {
+            assert!(self.reader.is_none(), "write() after starting to read");
+            self.writer.add_record(msg.as_bytes()).unwrap();
+        }
+
+        fn written_bytes(&self) -> usize {
+            self.dest.lock().unwrap().len()
+        }
+
+        fn new_reader(&self, initial_offset: u64) -> Reader<StringSource> {
+            let source = StringSource {
+                contents: self.dest.lock().unwrap().clone(),
+                pos: 0,
+                force_error: self.force_error,
+            };
+            let reporter = Box::new(ReportCollector(self.report.clone()));
+            Reader::new(source, Some(reporter), true, initial_offset)
+        }
+
+        fn start_reading_at(&mut self, initial_offset: u64) {
+            self.reader = Some(self.new_reader(initial_offset));
+        }
+
+        fn read(&mut self) -> String {
+            if self.reader.is_none() {
+                self.start_reading_at(0);
+            }
+            match self.reader.as_mut().unwrap().read_record() {
+                Some(record) => String::from_utf8(record.to_vec()).unwrap(),
+                None => "EOF".to_string(),
+            }
+        }
+
+        fn last_record_offset(&self) -> u64 {
+            self.reader.as_ref().unwrap().last_record_offset()
+        }
+
+        fn increment_byte(&mut self, offset: usize, delta: u8) {
+            let mut dest = self.dest.lock().unwrap();
+            dest[offset] = dest[offset].wrapping_add(delta);
+        }
+
+        fn set_byte(&mut self, offset: usize, new_byte: u8) {
+            self.dest.lock().unwrap()[offset] = new_byte;
+        }
+
+        fn shrink_size(&mut self, bytes: usize) {
+            let mut dest = self.dest.lock().unwrap();
+            let len = dest.len();
+            dest.truncate(len - bytes);
+        }
+
+        fn fix_checksum(&mut self, header_offset: usize, len: usize) {
+            // Compute crc of type/len/data
+            let mut dest = self.dest.lock().unwrap();
+            let crc = crc32c::value(&dest[header_offset + 6..header_offset + 6 + 1 + len]);
+            LittleEndian::write_u32(&mut dest[header_offset..], crc32c::mask(crc));
+        }
+
+        fn force_error(&mut self) {
+            self.force_error = true;
+        }
+
+        fn dropped_bytes(&self) -> usize {
+            self.report.borrow().dropped_bytes
+        }
+
+        fn report_message(&self) -> String {
+            self.report.borrow().message.clone()
+        }
+
+        // Returns OK iff recorded error message contains "msg"
+        fn match_error(&self, msg: &str) -> String {
+            let message = self.report_message();
+            if message.contains(msg) {
+                "OK".to_string()
+            } else {
+                message
+            }
+        }
+
+        fn write_initial_offset_log(&mut self) {
+            for (i, &size) in INITIAL_OFFSET_RECORD_SIZES.iter().enumerate() {
+                let record = big_string(&((b'a' + i as u8) as char).to_string(), size);
+                self.write(&record);
+            }
+        }
+
+        fn check_offset_past_end_returns_no_records(&mut self, offset_past_end: u64) {
+            self.write_initial_offset_log();
+            let offset = self.written_bytes() as u64 + offset_past_end;
+            let mut reader = self.new_reader(offset);
+            assert!(reader.read_record().is_none());
+        }
+
+        fn check_initial_offset_record(&mut self, initial_offset: u64, expected_record_offset: usize) {
+            self.write_initial_offset_log();
+            let mut reader = self.new_reader(initial_offset);
+
+            // Read all records from expected_record_offset through the last one.
+            assert!(expected_record_offset < INITIAL_OFFSET_RECORD_SIZES.len());
+            for i in expected_record_offset..INITIAL_OFFSET_RECORD_SIZES.len() {
+                let (len, first) = {
+                    let record = reader.read_record().unwrap();
+                    (record.len(), record[0])
+                };
+                assert_eq!(INITIAL_OFFSET_RECORD_SIZES[i], len);
+                assert_eq!(INITIAL_OFFSET_LAST_RECORD_OFFSETS[i], reader.last_record_offset());
+                assert_eq!(b'a' + i as u8, first);
+            }
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let mut t = LogTest::new();
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn read_write() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.write("bar");
+        t.write("");
+        t.write("xxxx");
+        assert_eq!("foo", t.read());
+        assert_eq!("bar", t.read());
+        assert_eq!("", t.read());
+        assert_eq!("xxxx", t.read());
+        assert_eq!("EOF", t.read());
+        assert_eq!("EOF", t.read()); // Make sure reads at eof work
+    }
+
+    #[test]
+    fn many_blocks() {
+        let mut t = LogTest::new();
+        for i in 0..100_000 {
+            t.write(&number_string(i));
+        }
+        for i in 0..100_000 {
+            assert_eq!(number_string(i), t.read());
+        }
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn fragmentation() {
+        let mut t = LogTest::new();
+        t.write("small");
+        t.write(&big_string("medium", 50000));
+        t.write(&big_string("large", 100_000));
+        assert_eq!("small", t.read());
+        assert_eq!(big_string("medium", 50000), t.read());
+        assert_eq!(big_string("large", 100_000), t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn marginal_trailer() {
+        // Make a trailer that is exactly the same length as an empty record.
+        let mut t = LogTest::new();
+        let n = BLOCK_SIZE - 2 * HEADER_SIZE;
+        t.write(&big_string("foo", n));
+        assert_eq!(BLOCK_SIZE - HEADER_SIZE, t.written_bytes());
+        t.write("");
+        t.write("bar");
+        assert_eq!(big_string("foo", n), t.read());
+        assert_eq!("", t.read());
+        assert_eq!("bar", t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn marginal_trailer2() {
+        // Make a trailer that is exactly the same length as an empty record.
+        let mut t = LogTest::new();
+        let n = BLOCK_SIZE - 2 * HEADER_SIZE;
+        t.write(&big_string("foo", n));
+        assert_eq!(BLOCK_SIZE - HEADER_SIZE, t.written_bytes());
+        t.write("bar");
+        assert_eq!(big_string("foo", n), t.read());
+        assert_eq!("bar", t.read());
+        assert_eq!("EOF", t.read());
+        assert_eq!(0, t.dropped_bytes());
+        assert_eq!("", t.report_message());
+    }
+
+    #[test]
+    fn short_trailer() {
+        let mut t = LogTest::new();
+        let n = BLOCK_SIZE - 2 * HEADER_SIZE + 4;
+        t.write(&big_string("foo", n));
+        assert_eq!(BLOCK_SIZE - HEADER_SIZE + 4, t.written_bytes());
+        t.write("");
+        t.write("bar");
+        assert_eq!(big_string("foo", n), t.read());
+        assert_eq!("", t.read());
+        assert_eq!("bar", t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn aligned_eof() {
+        let mut t = LogTest::new();
+        let n = BLOCK_SIZE - 2 * HEADER_SIZE + 4;
+        t.write(&big_string("foo", n));
+        assert_eq!(BLOCK_SIZE - HEADER_SIZE + 4, t.written_bytes());
+        assert_eq!(big_string("foo", n), t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn open_for_append() {
+        let mut t = LogTest::new();
+        t.write("hello");
+        t.reopen_for_append();
+        t.write("world");
+        assert_eq!("hello", t.read());
+        assert_eq!("world", t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn random_read() {
+        let mut t = LogTest::new();
+        const N: usize = 500;
+        let mut write_rnd = Random::new(301);
+        for i in 0..N {
+            t.write(&random_skewed_string(i, &mut write_rnd));
+        }
+        let mut read_rnd = Random::new(301);
+        for i in 0..N {
+            assert_eq!(random_skewed_string(i, &mut read_rnd), t.read());
+        }
+        assert_eq!("EOF", t.read());
+    }
+
+    // Tests of all the error paths in reader.rs follow:
+
+    #[test]
+    fn read_error() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.force_error();
+        assert_eq!("EOF", t.read());
+        assert_eq!(BLOCK_SIZE, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("read error"));
+    }
+
+    #[test]
+    fn bad_record_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        // Type is stored in header[6]
+        t.increment_byte(6, 100);
+        t.fix_checksum(0, 3);
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("unknown record type"));
+    }
+
+    #[test]
+    fn truncated_trailing_record_is_ignored() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.shrink_size(4); // Drop all payload as well as a header byte
+        assert_eq!("EOF", t.read());
+        // Truncated last record is ignored, not treated as an error.
+        assert_eq!(0, t.dropped_bytes());
+        assert_eq!("", t.report_message());
+    }
+
+    #[test]
+    fn bad_length() {
+        let mut t = LogTest::new();
+        let payload_size = BLOCK_SIZE - HEADER_SIZE;
+        t.write(&big_string("bar", payload_size));
+        t.write("foo");
+        // Least significant size byte is stored in header[4].
+        t.increment_byte(4, 1);
+        assert_eq!("foo", t.read());
+        assert_eq!(BLOCK_SIZE, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("bad record length"));
+    }
+
+    #[test]
+    fn bad_length_at_end_is_ignored() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.shrink_size(1);
+        assert_eq!("EOF", t.read());
+        assert_eq!(0, t.dropped_bytes());
+        assert_eq!("", t.report_message());
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.increment_byte(0, 10);
+        assert_eq!("EOF", t.read());
+        assert_eq!(10, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("checksum mismatch"));
+    }
+
+    #[test]
+    fn checksum_mismatch_is_ignored_without_checksum() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.increment_byte(0, 10);
+        let source = t.new_reader(0).file;
+        let mut reader = Reader::new(source, None, false, 0);
+        assert_eq!(reader.read_record(), Some(&b"foo"[..]));
+    }
+
+    #[test]
+    fn unexpected_middle_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.set_byte(6, RecordType::Middle as u8);
+        t.fix_checksum(0, 3);
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("missing start"));
+    }
+
+    #[test]
+    fn unexpected_last_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.set_byte(6, RecordType::Last as u8);
+        t.fix_checksum(0, 3);
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("missing start"));
+    }
+
+    #[test]
+    fn unexpected_full_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.write("bar");
+        t.set_byte(6, RecordType::First as u8);
+        t.fix_checksum(0, 3);
+        assert_eq!("bar", t.read());
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("partial record without end"));
+    }
+
+    #[test]
+    fn unexpected_first_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.write(&big_string("bar", 100_000));
+        t.set_byte(6, RecordType::First as u8);
+        t.fix_checksum(0, 3);
+        assert_eq!(big_string("bar", 100_000), t.read());
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("partial record without end"));
+    }
+
+    #[test]
+    fn missing_last_is_ignored() {
+        let mut t = LogTest::new();
+        t.write(&big_string("bar", BLOCK_SIZE));
+        // Remove the LAST block, including header.
+        t.shrink_size(14);
+        assert_eq!("EOF", t.read());
+        assert_eq!("", t.report_message());
+        assert_eq!(0, t.dropped_bytes());
+    }
+
+    #[test]
+    fn partial_last_is_ignored() {
+        let mut t = LogTest::new();
+        t.write(&big_string("bar", BLOCK_SIZE));
+        // Cause a bad record length in the LAST block.
+        t.shrink_size(1);
+        assert_eq!("EOF", t.read());
+        assert_eq!("", t.report_message());
+        assert_eq!(0, t.dropped_bytes());
+    }
+
+    #[test]
+    fn truncated_at_every_byte() {
+        // A writer crashing at any point loses only the records it had not
+        // finished, and never reports a corruption.
+        let mut t = LogTest::new();
+        let records = ["foo".to_string(), big_string("bar", 2 * BLOCK_SIZE), "baz".to_string()];
+        for record in &records {
+            t.write(record);
+        }
+        let full = t.dest.lock().unwrap().clone();
+        let ends = [10, 10 + 2 * BLOCK_SIZE + 3 * HEADER_SIZE, full.len()];
+        // every cut near a record or block boundary, and a sample in between
+        let near = |len: usize, mark: usize| len + 2 * HEADER_SIZE >= mark && len <= mark + 2 * HEADER_SIZE;
+        let cuts = (0..=full.len()).filter(|&len| {
+            len % 101 == 0
+                || ends.iter().any(|&end| near(len, end))
+                || (0..=3).any(|block| near(len, block * BLOCK_SIZE))
+        });
+        for len in cuts {
+            let source = StringSource { contents: full[..len].to_vec(), pos: 0, force_error: false };
+            let report = Rc::new(RefCell::new(Report::default()));
+            let reporter = Box::new(ReportCollector(report.clone()));
+            let mut reader = Reader::new(source, Some(reporter), true, 0);
+            let complete = ends.iter().filter(|&&end| end <= len).count();
+            for record in &records[..complete] {
+                assert_eq!(reader.read_record(), Some(record.as_bytes()), "{}", len);
+            }
+            assert_eq!(reader.read_record(), None, "{}", len);
+            assert_eq!(report.borrow().dropped_bytes, 0, "{}", len);
+        }
+    }
+
+    #[test]
+    fn skip_into_multi_record() {
+        // Consider a fragmented record:
+        //    first(R1), middle(R1), last(R1), first(R2)
+        // If initial_offset points to a record after first(R1) but before
+        // first(R2) incomplete fragment errors are not actual errors, and
+        // must be suppressed until a new first or full record is
+        // encountered.
+        let mut t = LogTest::new();
+        t.write(&big_string("foo", 3 * BLOCK_SIZE));
+        t.write("correct");
+        t.start_reading_at(BLOCK_SIZE as u64);
+
+        assert_eq!("correct", t.read());
+        assert_eq!("", t.report_message());
+        assert_eq!(0, t.dropped_bytes());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn error_joins_records() {
+        // Consider two fragmented records:
+        //    first(R1) last(R1) first(R2) last(R2)
+        // where the middle two fragments disappear. We do not want
+        // first(R1),last(R2) to get joined and returned as a valid record.
+        let mut t = LogTest::new();
+
+        // Write records that span two blocks
+        t.write(&big_string("foo", BLOCK_SIZE));
+        t.write(&big_string("bar", BLOCK_SIZE));
+        t.write("correct");
+
+        // Wipe the middle block
+        for offset in BLOCK_SIZE..2 * BLOCK_SIZE {
+            t.set_byte(offset, b'x');
+        }
+
+        assert_eq!("correct", t.read());
+        assert_eq!("EOF", t.read());
+        let dropped = t.dropped_bytes();
+        assert!(dropped <= 2 * BLOCK_SIZE + 100);
+        assert!(dropped >= 2 * BLOCK_SIZE);
+    }
+
+    #[test]
+    fn last_record_offset() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.write(&big_string("bar", BLOCK_SIZE));
+        t.write("baz");
+        assert_eq!("foo", t.read());
+        assert_eq!(0, t.last_record_offset());
+        assert_eq!(big_string("bar", BLOCK_SIZE), t.read());
+        assert_eq!(10, t.last_record_offset());
+        assert_eq!("baz", t.read());
+        assert_eq!((10 + BLOCK_SIZE + 2 * HEADER_SIZE) as u64, t.last_record_offset());
+    }
+
+    #[test]
+    fn read_start() {
+        LogTest::new().check_initial_offset_record(0, 0);
+    }
+
+    #[test]
+    fn read_second_one_off() {
+        LogTest::new().check_initial_offset_record(1, 1);
+    }
+
+    #[test]
+    fn read_second_ten_thousand() {
+        LogTest::new().check_initial_offset_record(10000, 1);
+    }
+
+    #[test]
+    fn read_second_start() {
+        LogTest::new().check_initial_offset_record(10007, 1);
+    }
+
+    #[test]
+    fn read_third_one_off() {
+        LogTest::new().check_initial_offset_record(10008, 2);
+    }
+
+    #[test]
+    fn read_third_start() {
+        LogTest::new().check_initial_offset_record(20014, 2);
+    }
+
+    #[test]
+    fn read_fourth_one_off() {
+        LogTest::new().check_initial_offset_record(20015, 3);
+    }
+
+    #[test]
+    fn read_fourth_first_block_trailer() {
+        LogTest::new().check_initial_offset_record(BLOCK_SIZE as u64 - 4, 3);
+    }
+
+    #[test]
+    fn read_fourth_middle_block() {
+        LogTest::new().check_initial_offset_record(BLOCK_SIZE as u64 + 1, 3);
+    }
+
+    #[test]
+    fn read_fourth_last_block() {
+        LogTest::new().check_initial_offset_record(2 * BLOCK_SIZE as u64 + 1, 3);
+    }
+
+    #[test]
+    fn read_fourth_start() {
+        LogTest::new().check_initial_offset_record(
+            (2 * (HEADER_SIZE + 1000) + (2 * BLOCK_SIZE - 1000) + 3 * HEADER_SIZE) as u64,
+            3,
+        );
+    }
+
+    #[test]
+    fn read_initial_offset_into_block_padding() {
+        LogTest::new().check_initial_offset_record(3 * BLOCK_SIZE as u64 - 3, 5);
+    }
+
+    #[test]
+    fn read_end() {
+        LogTest::new().check_offset_past_end_returns_no_records(0);
+    }
+
+    #[test]
+    fn read_past_end() {
+        LogTest::new().check_offset_past_end_returns_no_records(5);
+    }
+}
@@ -0,0 +1,189 @@
+use env::WritableFile;
+use error::Result;
+use log::{RecordType, BLOCK_SIZE, HEADER_SIZE, MAX_RECORD_TYPE};
+use util::coding::try_encode_fixed32;
+use util::crc32c;
+
+/// Appends records to a log file in LevelDB's block format.
+pub struct Writer<W> {
+    dest: W,
+    // Current offset in block
+    block_offset: usize,
+    // crc32c values for all supported record types. These are
+    // pre-computed to reduce the overhead of computing the crc of the
+    // record type stored in the header.
+    type_crc: [u32; MAX_RECORD_TYPE as usize + 1],
+}
+
+impl<W: WritableFile> Writer<W> {
+    /// Creates a writer that will append data to `dest`.
+    /// `dest` must be initially empty.
+    pub fn new(dest: W) -> Writer<W> {
+        Writer::with_dest_length(dest, 0)
+    }
+
+    /// Creates a writer that will append data to `dest`.
+    /// `dest` must have initial length `dest_length`.
+    pub fn with_dest_length(dest: W, dest_length: u64) -> Writer<W> {
+        let mut type_crc = [0; MAX_RECORD_TYPE as usize + 1];
+        for (t, crc) in type_crc.iter_mut().enumerate() {
+            *crc = crc32c::value(&[t as u8]);
+        }
+        Writer {
+            dest,
+            block_offset: (dest_length % BLOCK_SIZE as u64) as usize,
+            type_crc,
+        }
+    }
+
+    pub fn add_record(&mut self, data: &[u8]) -> Result<()> {
+        let mut left = data;
+
+        // Fragment the record if necessary and emit it. Note that if data
+        // is empty, we still want to iterate once to emit a single
+        // zero-length record
+        let mut begin = true;
+        loop {
+            let leftover = BLOCK_SIZE - self.block_offset;
+            if leftover < HEADER_SIZE {
+                // Switch to a new block
+                if leftover > 0 {
+                    // Fill the trailer
+                    self.dest.append(&[0; HEADER_SIZE - 1][..leftover])?;
+                }
+                self.block_offset = 0;
+            }
+
+            // Invariant: we never leave < HEADER_SIZE bytes in a block.
+            debug_assert!(BLOCK_SIZE - self.block_offset >= HEADER_SIZE);
+
+            let avail = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+            let fragment_length = left.len().min(avail);
+
+            let end = left.len() == fragment_length;
+            let record_type = match (begin, end) {
+                (true, true) => RecordType::Full,
+                (true, false) => RecordType::First,
+                (false, true) => RecordType::Last,
+                (false, false) => RecordType::Middle,
+            };
+
+            self.emit_physical_record(record_type, &left[..fragment_length])?;
+            left = &left[fragment_length..];
+            begin = false;
+            if left.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns the underlying destination.
+    pub fn get_ref(&self) -> &W {
+        &self.dest
+    }
+
+    /// Consumes the writer, returning the underlying destination.
+    pub fn into_inner(self) -> W {
+        self.dest
+    }
+
+    fn emit_physical_record(&mut self, t: RecordType, data: &[u8]) -> Result<()> {
+        let length = data.len();
+        // Must fit in two bytes
+        assert!(length <= 0xffff);
+        assert!(self.block_offset + HEADER_SIZE + length <= BLOCK_SIZE);
+
+        // Format the header
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[4] = (length & 0xff) as u8;
+        buf[5] = (length >> 8) as u8;
+        buf[6] = t as u8;
+
+        // Compute the crc of the record type and the payload.
+        let crc = crc32c::extend(self.type_crc[t as usize], data);
+        // Adjust for storage
+        try_encode_fixed32(&mut buf, crc32c::mask(crc));
+
+        // Write the header and the payload
+        self.dest.append(&buf)?;
+        self.dest.append(data)?;
+        self.dest.flush()?;
+        self.block_offset += HEADER_SIZE + length;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use env::{Env, MemEnv};
+
+    // Same payload as testdata/log/gen_golden.cc.
+    fn payload(n: usize) -> Vec<u8> {
+        (0..n).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn write(records: &[&[u8]]) -> Vec<u8> {
+        let env = MemEnv::new();
+        let name = Path::new("/log");
+        let mut writer = Writer::new(env.new_writable_file(name).unwrap());
+        for record in records {
+            writer.add_record(record).unwrap();
+        }
+        env.file_contents(name).unwrap()
+    }
+
+    #[test]
+    fn golden_empty() {
+        let golden = include_bytes!("../../testdata/log/empty.log");
+        assert_eq!(&write(&[b""])[..], &golden[..]);
+    }
+
+    #[test]
+    fn golden_full() {
+        let golden = include_bytes!("../../testdata/log/full.log");
+        assert_eq!(&write(&[b"foo", b"bar", &payload(1000)])[..], &golden[..]);
+    }
+
+    #[test]
+    fn golden_fragmented() {
+        let golden = include_bytes!("../../testdata/log/fragmented.log");
+        let got = write(&[b"small", &payload(100000), b"tail"]);
+        assert_eq!(got.len(), golden.len());
+        assert!(got[..] == golden[..]);
+        // FIRST at the start of the payload, MIDDLE and LAST at the start
+        // of the following blocks.
+        assert_eq!(got[12 + 6], RecordType::First as u8);
+        assert_eq!(got[BLOCK_SIZE + 6], RecordType::Middle as u8);
+        assert_eq!(got[2 * BLOCK_SIZE + 6], RecordType::Middle as u8);
+        assert_eq!(got[3 * BLOCK_SIZE + 6], RecordType::Last as u8);
+    }
+
+    #[test]
+    fn golden_trailer() {
+        let golden = include_bytes!("../../testdata/log/trailer.log");
+        let first = payload(BLOCK_SIZE - 2 * HEADER_SIZE - 3);
+        let got = write(&[&first, b"", b"after trailer"]);
+        assert_eq!(got.len(), golden.len());
+        assert!(got[..] == golden[..]);
+        assert_eq!(&got[BLOCK_SIZE - 3..BLOCK_SIZE], &[0, 0, 0]);
+    }
+
+    #[test]
+    fn reopen_continues_block_offset() {
+        let env = MemEnv::new();
+        let name = Path::new("/log");
+        let first = payload(BLOCK_SIZE - 2 * HEADER_SIZE - 3);
+        let mut writer = Writer::new(env.new_writable_file(name).unwrap());
+        writer.add_record(&first).unwrap();
+        writer.add_record(b"").unwrap();
+        drop(writer);
+        let length = env.get_file_size(name).unwrap();
+        let mut writer = Writer::with_dest_length(env.new_appendable_file(name).unwrap(), length);
+        writer.add_record(b"after trailer").unwrap();
+        let golden = include_bytes!("../../testdata/log/trailer.log");
+        assert!(env.file_contents(name).unwrap()[..] == golden[..]);
+    }
+}
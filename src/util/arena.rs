@@ -0,0 +1,210 @@
+use std::alloc::{self, Layout};
+use std::cell::UnsafeCell;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const BLOCK_SIZE: usize = 4096;
+// alignment of `allocate_aligned` and of every block
+const ALIGN: usize = if mem::size_of::<*const u8>() > 8 {
+    mem::size_of::<*const u8>()
+} else {
+    8
+};
+
+struct Inner {
+    // allocation state
+    alloc_ptr: *mut u8,
+    alloc_bytes_remaining: usize,
+    // every block handed out by the global allocator, freed on drop
+    blocks: Vec<(*mut u8, Layout)>,
+}
+
+/// A bump allocator that hands out byte regions carved from large blocks.
+///
+/// Memory is only released when the arena is dropped. Allocation takes
+/// `&self` so that the regions can be handed out while earlier ones are still
+/// borrowed; the arena is not `Sync`, so only one thread allocates at a time.
+pub struct Arena {
+    inner: UnsafeCell<Inner>,
+    // total memory usage of the arena, readable from any thread
+    memory_usage: AtomicUsize,
+}
+
+unsafe impl Send for Arena {}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena {
+            inner: UnsafeCell::new(Inner {
+                alloc_ptr: ptr::null_mut(),
+                alloc_bytes_remaining: 0,
+                blocks: Vec::new(),
+            }),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns a pointer to a newly allocated, zeroed memory block of `bytes`
+    /// bytes. The memory stays valid for the lifetime of the arena.
+    pub fn allocate(&self, bytes: usize) -> *mut u8 {
+        // The semantics of what to return are a bit messy if we allow
+        // 0-byte allocations, so we disallow them here (we don't need
+        // them for our internal use).
+        assert!(bytes > 0);
+        let inner = unsafe { &mut *self.inner.get() };
+        if bytes <= inner.alloc_bytes_remaining {
+            let result = inner.alloc_ptr;
+            inner.alloc_ptr = unsafe { inner.alloc_ptr.add(bytes) };
+            inner.alloc_bytes_remaining -= bytes;
+            return result;
+        }
+        self.allocate_fallback(inner, bytes)
+    }
+
+    /// Like `allocate`, but the result is aligned to at least 8 bytes and
+    /// to the alignment of a pointer.
+    pub fn allocate_aligned(&self, bytes: usize) -> *mut u8 {
+        let inner = unsafe { &mut *self.inner.get() };
+        let current_mod = inner.alloc_ptr as usize & (ALIGN - 1);
+        let slop = if current_mod == 0 { 0 } else { ALIGN - current_mod };
+        let needed = bytes + slop;
+        let result = if needed <= inner.alloc_bytes_remaining {
+            let result = unsafe { inner.alloc_ptr.add(slop) };
+            inner.alloc_ptr = unsafe { inner.alloc_ptr.add(needed) };
+            inner.alloc_bytes_remaining -= needed;
+            result
+        } else {
+            // allocate_fallback always returns aligned memory
+            self.allocate_fallback(inner, bytes)
+        };
+        debug_assert_eq!(result as usize & (ALIGN - 1), 0);
+        result
+    }
+
+    /// Allocates `bytes` bytes and returns them as a slice that encoders can
+    /// write into directly.
+    #[allow(clippy::mut_from_ref)]
+    pub fn allocate_bytes(&self, bytes: usize) -> &mut [u8] {
+        // every allocation is a distinct region, so no other reference to
+        // these bytes exists
+        unsafe { slice::from_raw_parts_mut(self.allocate(bytes), bytes) }
+    }
+
+    /// Returns an estimate of the total memory used by the arena.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    fn allocate_fallback(&self, inner: &mut Inner, bytes: usize) -> *mut u8 {
+        if bytes > BLOCK_SIZE / 4 {
+            // Object is more than a quarter of our block size. Allocate it
+            // separately to avoid wasting too much space in leftover bytes.
+            return self.allocate_new_block(inner, bytes);
+        }
+
+        // We waste the remaining space in the current block.
+        inner.alloc_ptr = self.allocate_new_block(inner, BLOCK_SIZE);
+        inner.alloc_bytes_remaining = BLOCK_SIZE;
+
+        let result = inner.alloc_ptr;
+        inner.alloc_ptr = unsafe { inner.alloc_ptr.add(bytes) };
+        inner.alloc_bytes_remaining -= bytes;
+        result
+    }
+
+    fn allocate_new_block(&self, inner: &mut Inner, block_bytes: usize) -> *mut u8 {
+        let layout = Layout::from_size_align(block_bytes, ALIGN)
+            .expect("arena block too large");
+        let result = unsafe { alloc::alloc_zeroed(layout) };
+        if result.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        inner.blocks.push((result, layout));
+        self.memory_usage.fetch_add(
+            block_bytes + mem::size_of::<(*mut u8, Layout)>(),
+            Ordering::Relaxed,
+        );
+        result
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Arena {
+        Arena::new()
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for &(block, layout) in &self.inner.get_mut().blocks {
+            unsafe { alloc::dealloc(block, layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::random::Random;
+
+    #[test]
+    fn empty() {
+        let arena = Arena::new();
+        assert_eq!(arena.memory_usage(), 0);
+    }
+
+    #[test]
+    fn simple() {
+        let mut allocated: Vec<(usize, *mut u8)> = Vec::new();
+        let arena = Arena::new();
+        const N: usize = 100_000;
+        let mut bytes = 0;
+        let mut last_usage = 0;
+        let mut rnd = Random::new(301);
+        for i in 0..N {
+            let mut s = if i % (N / 10) == 0 {
+                i
+            } else if rnd.one_in(4000) {
+                rnd.uniform(6000) as usize
+            } else if rnd.one_in(10) {
+                rnd.uniform(100) as usize
+            } else {
+                rnd.uniform(20) as usize
+            };
+            if s == 0 {
+                // Our arena disallows size 0 allocations.
+                s = 1;
+            }
+            let r = if rnd.one_in(10) {
+                let r = arena.allocate_aligned(s);
+                assert_eq!(r as usize & 7, 0);
+                r
+            } else {
+                arena.allocate(s)
+            };
+
+            // Fill the "i"th allocation with a known bit pattern
+            for b in 0..s {
+                unsafe { *r.add(b) = (i % 256) as u8 };
+            }
+            bytes += s;
+            allocated.push((s, r));
+
+            let usage = arena.memory_usage();
+            assert!(usage >= last_usage);
+            assert!(usage >= bytes);
+            if i > N / 10 {
+                assert!(usage as f64 <= bytes as f64 * 1.10);
+            }
+            last_usage = usage;
+        }
+        for (i, &(num_bytes, p)) in allocated.iter().enumerate() {
+            for b in 0..num_bytes {
+                // Check the "i"th allocation for the known bit pattern
+                assert_eq!(unsafe { *p.add(b) } as usize, i % 256);
+            }
+        }
+    }
+}
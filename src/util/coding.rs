@@ -1,77 +1,58 @@
+use std::error;
+use std::fmt;
 use std::mem;
-use std::ptr;
+use std::result;
+use std::slice;
 
-#[cfg(target_endian = "little")]
-const IS_LITTLE_ENDIAN: bool = true;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
 
-#[cfg(target_endian = "big")]
-const IS_LITTLE_ENDIAN: bool = false;
+use error::{Error, Result};
 
-macro_rules! encode_fixed {
-    ($T: ty, $buf: expr, $value: expr) => {
-        if IS_LITTLE_ENDIAN {
-            let pbuf = mem::transmute($buf);
-            ptr::copy_nonoverlapping(&$value, pbuf, mem::size_of::<$T>());
-        } else {
-            let mut p = $buf;
-            for _ in 0..mem::size_of::<$T>() {
-                *p = $value as u8;
-                $value >>= 8;
-                p = p.offset(1);
-            }
-        }
-    };
-}
-
-macro_rules! decode_fixed {
-    ($T: ty, $buf: expr) => {
-        {
-            let mut result: $T = mem::uninitialized();
-            if IS_LITTLE_ENDIAN {
-                let psrc = $buf.as_ptr() as *const $T;
-                ptr::copy_nonoverlapping(psrc, &mut result, mem::size_of::<$T>());
-            } else {
-                for i in 0..mem::size_of::<$T>() {
-                    result |= $buf[i] as $T << 8*i
-                }
-            }
-            result
-        }
-    };
-}
+pub const MAX_VARINT32_LENGTH: usize = 5;
+pub const MAX_VARINT64_LENGTH: usize = 10;
 
 macro_rules! encode_var {
     ($T: ty, $buf: expr, $value: expr) => {
         {
-            static B: $T= 128;
-            let mut p = $buf;
-            while $value >= B {
-                *p = ($value | B) as u8;
+            const B: $T = 128;
+            let mut i = 0;
+            loop {
+                if i >= $buf.len() {
+                    return None;
+                }
+                if $value < B {
+                    $buf[i] = $value as u8;
+                    return Some(i + 1);
+                }
+                $buf[i] = ($value | B) as u8;
                 $value >>= 7;
-                p = p.offset(1);
+                i += 1;
             }
-            *p = $value as u8;
-            p.offset_to($buf).unwrap() as usize + 1
         }
     };
 }
 
 macro_rules! get_varint {
-    ($T: ty, $input: expr, $max_index: expr) => {
+    ($T: ty, $name: expr, $input: expr, $max_index: expr) => {
         {
+            const BITS: usize = mem::size_of::<$T>() * 8;
             let mut result: $T = 0;
-            for (i, byte) in $input.iter().enumerate() {
+            for (i, &byte) in $input.iter().enumerate() {
                 if i >= $max_index {
-                    return None;
+                    return Err(Error::corruption(concat!($name, " is too long")));
+                }
+                let shift = 7 * i;
+                let bits = (byte & 127) as $T;
+                // the last byte may only carry the bits that are left
+                if shift + 7 > BITS && bits >> (BITS - shift) != 0 {
+                    return Err(Error::corruption(concat!($name, " overflows")));
                 }
-                if (byte & 128) !=0 {
-                    result |= ((byte & 127) << 7*i) as $T;
-                } else {
-                    result |= (byte << 7*i) as $T;
-                    return Some((&$input[i+1..], result))
+                result |= bits << shift;
+                if (byte & 128) == 0 {
+                    return Ok((&$input[i+1..], result))
                 }
             }
-            None
+            Err(Error::corruption(concat!("truncated ", $name)))
         }
     };
 }
@@ -90,62 +71,141 @@ macro_rules! varint_length {
 }
 
 pub fn put_fixed32(dst: &mut Vec<u8>, value: u32) {
-    unsafe {
-        let mut buf: [u8; 4] = mem::uninitialized();
-        encode_fixed32(buf.as_mut_ptr(), value);
-        dst.extend_from_slice(&buf);
-    }
+    let mut buf = [0u8; 4];
+    try_encode_fixed32(&mut buf, value);
+    dst.extend_from_slice(&buf);
 }
 
 pub fn put_fixed64(dst: &mut Vec<u8>, value: u64) {
-    unsafe {
-        let mut buf: [u8; 8] = mem::uninitialized();
-        encode_fixed64(buf.as_mut_ptr(), value);
-        dst.extend_from_slice(&buf);
-    }
+    let mut buf = [0u8; 8];
+    try_encode_fixed64(&mut buf, value);
+    dst.extend_from_slice(&buf);
 }
 
 pub fn put_varint32(dst: &mut Vec<u8>, value: u32) {
-    unsafe {
-        let mut buf: [u8; 5] = mem::uninitialized();
-        let length = encode_varint32(buf.as_mut_ptr(), value);
-        dst.extend_from_slice(&buf[0..length]);
-    }
+    let mut buf = [0u8; MAX_VARINT32_LENGTH];
+    let length = try_encode_varint32(&mut buf, value).unwrap();
+    dst.extend_from_slice(&buf[..length]);
 }
 
 pub fn put_varint64(dst: &mut Vec<u8>, value: u64) {
-    unsafe {
-        let mut buf: [u8; 10] = mem::uninitialized();
-        let length = encode_varint64(buf.as_mut_ptr(), value);
-        dst.extend_from_slice(&buf[0..length]);
-    }
+    let mut buf = [0u8; MAX_VARINT64_LENGTH];
+    let length = try_encode_varint64(&mut buf, value).unwrap();
+    dst.extend_from_slice(&buf[..length]);
 }
 
-pub fn put_length_prefixed_slice(dst: &mut Vec<u8>, value: &[u8]) {
+/// Appends `value` prefixed with its length as a varint32.
+///
+/// Fails without touching `dst` if `value` is longer than `u32::MAX` bytes;
+/// use `put_length_prefixed_slice64` for such values.
+pub fn put_length_prefixed_slice(dst: &mut Vec<u8>, value: &[u8]) -> result::Result<(), SliceTooLong> {
+    if value.len() as u64 > u64::from(u32::MAX) {
+        return Err(SliceTooLong { len: value.len(), max: u32::MAX as usize });
+    }
     put_varint32(dst, value.len() as u32);
-    dst.extend_from_slice(&value[0..value.len()]);
+    dst.extend_from_slice(value);
+    Ok(())
+}
+
+pub fn put_length_prefixed_slice64(dst: &mut Vec<u8>, value: &[u8]) {
+    put_varint64(dst, value.len() as u64);
+    dst.extend_from_slice(value);
 }
 
 
-pub fn get_varint32(input: &[u8]) -> Option<(&[u8], u32)> {
-    get_varint!(u32, input, 5)
+pub fn get_varint32(input: &[u8]) -> Result<(&[u8], u32)> {
+    get_varint!(u32, "varint32", input, MAX_VARINT32_LENGTH)
+}
+
+pub fn get_varint64(input: &[u8]) -> Result<(&[u8], u64)> {
+    get_varint!(u64, "varint64", input, MAX_VARINT64_LENGTH)
 }
 
-pub fn get_varint64(input: &[u8]) -> Option<(&[u8], u64)> {
-    get_varint!(u64, input, 10)
+pub fn get_length_prefixed_slice(input: &[u8]) -> Result<(&[u8], &[u8])> {
+    get_varint32(input).and_then(|(remain, len)| split_slice(remain, len as u64))
 }
 
-pub fn get_length_prefixed_slice(input: &[u8]) -> Option<(&[u8], &[u8])> {
+pub fn get_length_prefixed_slice64(input: &[u8]) -> Result<(&[u8], &[u8])> {
+    get_varint64(input).and_then(|(remain, len)| split_slice(remain, len))
+}
+
+/// Like `get_length_prefixed_slice`, but refuses any encoded length above
+/// `max_len` before looking at the data, so corrupt input cannot make the
+/// caller accept an absurdly large value.
+pub fn get_length_prefixed_slice_bounded(input: &[u8], max_len: usize) -> Result<(&[u8], &[u8])> {
     get_varint32(input).and_then(|(remain, len)| {
-        let len = len as usize;
-        if remain.len() >= len {
-            Some((&remain[len..], &remain[..len]))
+        if len as u64 > max_len as u64 {
+            Err(Error::corruption(format!(
+                "slice length {} exceeds the limit {}",
+                len, max_len
+            )))
         } else {
-            None
+            split_slice(remain, len as u64)
         }
     })
 }
 
+fn split_slice(input: &[u8], len: u64) -> Result<(&[u8], &[u8])> {
+    if input.len() as u64 >= len {
+        let len = len as usize;
+        Ok((&input[len..], &input[..len]))
+    } else {
+        Err(Error::corruption(format!(
+            "truncated slice: length {} but {} bytes left",
+            len,
+            input.len()
+        )))
+    }
+}
+
+/// Decodes `out.len()` consecutive varint32 values from `input`, returning
+/// the bytes that follow them.
+///
+/// Whenever at least 8 bytes of input are left the values are decoded a
+/// machine word at a time; the tail falls back to `get_varint32`. Fails
+/// under the same conditions as `get_varint32`.
+pub fn decode_varint32_batch<'a>(mut input: &'a [u8], out: &mut [u32]) -> Result<&'a [u8]> {
+    const MSBS: u64 = 0x8080_8080_8080_8080;
+    let mut i = 0;
+    while i < out.len() {
+        if input.len() < 8 {
+            let (remain, value) = get_varint32(input)?;
+            out[i] = value;
+            input = remain;
+            i += 1;
+            continue;
+        }
+        let word = LittleEndian::read_u64(input);
+        let stops = !word & MSBS;
+        if stops == MSBS && out.len() - i >= 8 {
+            // eight single byte varints
+            for (j, value) in out[i..i + 8].iter_mut().enumerate() {
+                *value = (word >> (8 * j)) as u32 & 0x7f;
+            }
+            input = &input[8..];
+            i += 8;
+            continue;
+        }
+        let len = (stops.trailing_zeros() / 8 + 1) as usize;
+        if len > MAX_VARINT32_LENGTH {
+            return Err(Error::corruption("varint32 is too long"));
+        }
+        // keep the payload bits up to and including the terminating byte, then
+        // squeeze the 7-bit groups together: 8 -> 16 -> 32 -> 64 bit lanes
+        let mut bits = word & (stops ^ (stops - 1)) & 0x7f7f_7f7f_7f7f_7f7f;
+        bits = (bits & 0x007f_007f_007f_007f) | ((bits & 0x7f00_7f00_7f00_7f00) >> 1);
+        bits = (bits & 0x0000_3fff_0000_3fff) | ((bits & 0x3fff_0000_3fff_0000) >> 2);
+        bits = (bits & 0x0000_0000_0fff_ffff) | ((bits & 0x0fff_ffff_0000_0000) >> 4);
+        if bits > u64::from(u32::MAX) {
+            return Err(Error::corruption("varint32 overflows"));
+        }
+        out[i] = bits as u32;
+        input = &input[len..];
+        i += 1;
+    }
+    Ok(input)
+}
+
 pub fn varint32_length(mut value: u32) -> usize {
     varint_length!(value)
 }
@@ -154,28 +214,1411 @@ pub fn varint64_length(mut value: u64) -> usize {
     varint_length!(value)
 }
 
-pub unsafe fn encode_fixed32(buf: *mut u8, mut value: u32) {
-    encode_fixed!(u32, buf, value);
+// Signed varints are zigzag encoded so that values of small magnitude stay
+// short regardless of sign: 0, -1, 1, -2, ... map to 0, 1, 2, 3, ...
+#[inline]
+fn zigzag_encode32(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+#[inline]
+fn zigzag_encode64(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
 }
 
-pub unsafe fn encode_fixed64(buf: *mut u8, mut value: u64) {
-    encode_fixed!(u64, buf, value);
+#[inline]
+fn zigzag_decode32(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
 }
 
-pub unsafe fn encode_varint32(buf: *mut u8, mut value: u32) -> usize {
+#[inline]
+fn zigzag_decode64(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+pub fn put_varint_signed32(dst: &mut Vec<u8>, value: i32) {
+    put_varint32(dst, zigzag_encode32(value));
+}
+
+pub fn put_varint_signed64(dst: &mut Vec<u8>, value: i64) {
+    put_varint64(dst, zigzag_encode64(value));
+}
+
+pub fn get_varint_signed32(input: &[u8]) -> Result<(&[u8], i32)> {
+    get_varint32(input).map(|(remain, value)| (remain, zigzag_decode32(value)))
+}
+
+pub fn get_varint_signed64(input: &[u8]) -> Result<(&[u8], i64)> {
+    get_varint64(input).map(|(remain, value)| (remain, zigzag_decode64(value)))
+}
+
+pub fn varint_signed32_length(value: i32) -> usize {
+    varint32_length(zigzag_encode32(value))
+}
+
+pub fn varint_signed64_length(value: i64) -> usize {
+    varint64_length(zigzag_encode64(value))
+}
+
+/// Encodes `value` as 4 little-endian bytes at the front of `buf`.
+///
+/// Returns the number of bytes written, or `None` if `buf` is too short.
+pub fn try_encode_fixed32(buf: &mut [u8], value: u32) -> Option<usize> {
+    if buf.len() < 4 {
+        return None;
+    }
+    LittleEndian::write_u32(buf, value);
+    Some(4)
+}
+
+/// Encodes `value` as 8 little-endian bytes at the front of `buf`.
+///
+/// Returns the number of bytes written, or `None` if `buf` is too short.
+pub fn try_encode_fixed64(buf: &mut [u8], value: u64) -> Option<usize> {
+    if buf.len() < 8 {
+        return None;
+    }
+    LittleEndian::write_u64(buf, value);
+    Some(8)
+}
+
+/// Encodes `value` as a varint at the front of `buf`.
+///
+/// Returns the number of bytes written, or `None` if `buf` is too short.
+pub fn try_encode_varint32(buf: &mut [u8], mut value: u32) -> Option<usize> {
     encode_var!(u32, buf, value)
 }
 
-pub unsafe fn encode_varint64(buf: *mut u8, mut value: u64) -> usize {
+/// Encodes `value` as a varint at the front of `buf`.
+///
+/// Returns the number of bytes written, or `None` if `buf` is too short.
+pub fn try_encode_varint64(buf: &mut [u8], mut value: u64) -> Option<usize> {
     encode_var!(u64, buf, value)
 }
 
+/// Decodes a little-endian `u32` from the first 4 bytes of `input`.
+///
+/// Fails if `input` is shorter than 4 bytes.
+#[inline]
+pub fn try_decode_fixed32(input: &[u8]) -> Result<u32> {
+    if input.len() < 4 {
+        return Err(Error::corruption("truncated fixed32"));
+    }
+    Ok(LittleEndian::read_u32(input))
+}
+
+/// Decodes a little-endian `u64` from the first 8 bytes of `input`.
+///
+/// Fails if `input` is shorter than 8 bytes.
+#[inline]
+pub fn try_decode_fixed64(input: &[u8]) -> Result<u64> {
+    if input.len() < 8 {
+        return Err(Error::corruption("truncated fixed64"));
+    }
+    Ok(LittleEndian::read_u64(input))
+}
+
+/// # Safety
+///
+/// `buf` must be valid for writes of 4 bytes.
+pub unsafe fn encode_fixed32(buf: *mut u8, value: u32) {
+    try_encode_fixed32(slice::from_raw_parts_mut(buf, 4), value);
+}
+
+/// # Safety
+///
+/// `buf` must be valid for writes of 8 bytes.
+pub unsafe fn encode_fixed64(buf: *mut u8, value: u64) {
+    try_encode_fixed64(slice::from_raw_parts_mut(buf, 8), value);
+}
+
+/// # Safety
+///
+/// `buf` must be valid for writes of `MAX_VARINT32_LENGTH` bytes.
+pub unsafe fn encode_varint32(buf: *mut u8, value: u32) -> usize {
+    try_encode_varint32(slice::from_raw_parts_mut(buf, MAX_VARINT32_LENGTH), value).unwrap()
+}
+
+/// # Safety
+///
+/// `buf` must be valid for writes of `MAX_VARINT64_LENGTH` bytes.
+pub unsafe fn encode_varint64(buf: *mut u8, value: u64) -> usize {
+    try_encode_varint64(slice::from_raw_parts_mut(buf, MAX_VARINT64_LENGTH), value).unwrap()
+}
+
+/// # Safety
+///
+/// `input` must be at least 4 bytes long.
 #[inline]
 pub unsafe fn decode_fixed32(input: &[u8]) -> u32 {
-    decode_fixed!(u32, input)
+    LittleEndian::read_u32(input)
 }
 
+/// # Safety
+///
+/// `input` must be at least 8 bytes long.
 #[inline]
 pub unsafe fn decode_fixed64(input: &[u8]) -> u64 {
-    decode_fixed!(u64, input)
+    LittleEndian::read_u64(input)
+}
+
+// Order-preserving encodings. The little-endian formats above are what the
+// on-disk structures use; the functions below produce bytes whose bytewise
+// order matches the logical order of the encoded values, for building user
+// keys out of several fields.
+
+pub fn put_fixed32_be(dst: &mut Vec<u8>, value: u32) {
+    let mut buf = [0u8; 4];
+    BigEndian::write_u32(&mut buf, value);
+    dst.extend_from_slice(&buf);
+}
+
+pub fn put_fixed64_be(dst: &mut Vec<u8>, value: u64) {
+    let mut buf = [0u8; 8];
+    BigEndian::write_u64(&mut buf, value);
+    dst.extend_from_slice(&buf);
+}
+
+pub fn get_fixed32_be(input: &[u8]) -> Result<(&[u8], u32)> {
+    if input.len() < 4 {
+        return Err(Error::corruption("truncated big-endian fixed32"));
+    }
+    Ok((&input[4..], BigEndian::read_u32(input)))
+}
+
+pub fn get_fixed64_be(input: &[u8]) -> Result<(&[u8], u64)> {
+    if input.len() < 8 {
+        return Err(Error::corruption("truncated big-endian fixed64"));
+    }
+    Ok((&input[8..], BigEndian::read_u64(input)))
+}
+
+pub fn put_ordered_i32(dst: &mut Vec<u8>, value: i32) {
+    put_fixed32_be(dst, value as u32 ^ (1 << 31));
+}
+
+pub fn put_ordered_i64(dst: &mut Vec<u8>, value: i64) {
+    put_fixed64_be(dst, value as u64 ^ (1 << 63));
+}
+
+pub fn get_ordered_i32(input: &[u8]) -> Result<(&[u8], i32)> {
+    get_fixed32_be(input).map(|(remain, value)| (remain, (value ^ (1 << 31)) as i32))
+}
+
+pub fn get_ordered_i64(input: &[u8]) -> Result<(&[u8], i64)> {
+    get_fixed64_be(input).map(|(remain, value)| (remain, (value ^ (1 << 63)) as i64))
+}
+
+// Positive floats only need their sign bit set to sort above the negatives;
+// negative floats have every bit flipped so that larger magnitudes sort lower.
+// NaNs with the sign bit clear sort after +inf, those with it set before -inf.
+
+pub fn put_ordered_f32(dst: &mut Vec<u8>, value: f32) {
+    let bits = value.to_bits();
+    let bits = if bits >> 31 == 1 { !bits } else { bits | (1 << 31) };
+    put_fixed32_be(dst, bits);
+}
+
+pub fn put_ordered_f64(dst: &mut Vec<u8>, value: f64) {
+    let bits = value.to_bits();
+    let bits = if bits >> 63 == 1 { !bits } else { bits | (1 << 63) };
+    put_fixed64_be(dst, bits);
+}
+
+pub fn get_ordered_f32(input: &[u8]) -> Result<(&[u8], f32)> {
+    get_fixed32_be(input).map(|(remain, bits)| {
+        let bits = if bits >> 31 == 1 { bits & !(1 << 31) } else { !bits };
+        (remain, f32::from_bits(bits))
+    })
+}
+
+pub fn get_ordered_f64(input: &[u8]) -> Result<(&[u8], f64)> {
+    get_fixed64_be(input).map(|(remain, bits)| {
+        let bits = if bits >> 63 == 1 { bits & !(1 << 63) } else { !bits };
+        (remain, f64::from_bits(bits))
+    })
+}
+
+// Byte strings are escaped so that they can be followed by further fields:
+// every 0x00 becomes 0x00 0xff and the string ends with 0x00 0x01. A string
+// that is a prefix of another therefore sorts before it.
+const ORDERED_ESCAPE: u8 = 0x00;
+const ORDERED_ESCAPED_NUL: u8 = 0xff;
+const ORDERED_TERMINATOR: u8 = 0x01;
+
+pub fn put_ordered_bytes(dst: &mut Vec<u8>, value: &[u8]) {
+    for &byte in value {
+        dst.push(byte);
+        if byte == ORDERED_ESCAPE {
+            dst.push(ORDERED_ESCAPED_NUL);
+        }
+    }
+    dst.push(ORDERED_ESCAPE);
+    dst.push(ORDERED_TERMINATOR);
+}
+
+pub fn get_ordered_bytes(input: &[u8]) -> Result<(&[u8], Vec<u8>)> {
+    let mut value = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let byte = input[i];
+        if byte != ORDERED_ESCAPE {
+            value.push(byte);
+            i += 1;
+            continue;
+        }
+        match input.get(i + 1) {
+            Some(&ORDERED_ESCAPED_NUL) => value.push(ORDERED_ESCAPE),
+            Some(&ORDERED_TERMINATOR) => return Ok((&input[i + 2..], value)),
+            Some(&b) => {
+                return Err(Error::corruption(format!(
+                    "bad escape sequence 0x00 0x{:02x} in ordered bytes",
+                    b
+                )))
+            }
+            None => break,
+        }
+        i += 2;
+    }
+    Err(Error::corruption("unterminated ordered bytes"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    Truncated,
+    Malformed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub field: &'static str,
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            DecodeErrorKind::Truncated => "truncated",
+            DecodeErrorKind::Malformed => "malformed",
+        };
+        write!(f, "{} {} at offset {}", kind, self.field, self.offset)
+    }
+}
+
+impl error::Error for DecodeError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SliceTooLong {
+    pub len: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for SliceTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "slice of {} bytes exceeds the maximum length {}", self.len, self.max)
+    }
+}
+
+impl error::Error for SliceTooLong {}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Error {
+        Error::Corruption(e.to_string())
+    }
+}
+
+impl From<SliceTooLong> for Error {
+    fn from(e: SliceTooLong) -> Error {
+        Error::InvalidArgument(e.to_string())
+    }
+}
+
+/// A cursor over an encoded buffer that consumes one field at a time.
+#[derive(Debug, Clone)]
+pub struct Decoder<'a> {
+    input: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(input: &'a [u8]) -> Decoder<'a> {
+        Decoder { input, offset: 0 }
+    }
+
+    /// Bytes that have not been consumed yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.input
+    }
+
+    /// Number of bytes consumed since the decoder was created.
+    pub fn position(&self) -> usize {
+        self.offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    pub fn read_u8(&mut self, field: &'static str) -> result::Result<u8, DecodeError> {
+        let value = *self
+            .input
+            .first()
+            .ok_or_else(|| self.error(field, DecodeErrorKind::Truncated))?;
+        self.advance(1);
+        Ok(value)
+    }
+
+    pub fn read_fixed32(&mut self, field: &'static str) -> result::Result<u32, DecodeError> {
+        let value = try_decode_fixed32(self.input)
+            .map_err(|_| self.error(field, DecodeErrorKind::Truncated))?;
+        self.advance(4);
+        Ok(value)
+    }
+
+    pub fn read_fixed64(&mut self, field: &'static str) -> result::Result<u64, DecodeError> {
+        let value = try_decode_fixed64(self.input)
+            .map_err(|_| self.error(field, DecodeErrorKind::Truncated))?;
+        self.advance(8);
+        Ok(value)
+    }
+
+    pub fn read_varint32(&mut self, field: &'static str) -> result::Result<u32, DecodeError> {
+        match get_varint32(self.input) {
+            Ok((remain, value)) => {
+                self.consume_to(remain);
+                Ok(value)
+            }
+            Err(_) => Err(self.varint_error(field, MAX_VARINT32_LENGTH)),
+        }
+    }
+
+    pub fn read_varint64(&mut self, field: &'static str) -> result::Result<u64, DecodeError> {
+        match get_varint64(self.input) {
+            Ok((remain, value)) => {
+                self.consume_to(remain);
+                Ok(value)
+            }
+            Err(_) => Err(self.varint_error(field, MAX_VARINT64_LENGTH)),
+        }
+    }
+
+    pub fn read_length_prefixed_slice(&mut self, field: &'static str) -> result::Result<&'a [u8], DecodeError> {
+        let start = self.clone();
+        let len = self.read_varint32(field)? as usize;
+        if self.input.len() < len {
+            *self = start;
+            return Err(self.error(field, DecodeErrorKind::Truncated));
+        }
+        let value = &self.input[..len];
+        self.advance(len);
+        Ok(value)
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.input = &self.input[n..];
+        self.offset += n;
+    }
+
+    fn consume_to(&mut self, remain: &'a [u8]) {
+        let n = self.input.len() - remain.len();
+        self.advance(n);
+    }
+
+    fn varint_error(&self, field: &'static str, max_length: usize) -> DecodeError {
+        // a varint that runs off the end of the input is truncated, one that
+        // terminates (or exceeds its maximum length) but does not fit is malformed
+        let terminated = self.input.iter().take(max_length).any(|&b| b & 128 == 0);
+        if !terminated && self.input.len() < max_length {
+            self.error(field, DecodeErrorKind::Truncated)
+        } else {
+            self.error(field, DecodeErrorKind::Malformed)
+        }
+    }
+
+    fn error(&self, field: &'static str, kind: DecodeErrorKind) -> DecodeError {
+        DecodeError { field, offset: self.offset, kind }
+    }
+}
+
+/// Accumulates the exact number of bytes a sequence of `Encoder` writes will
+/// produce, so the destination can be sized before anything is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncodedLen(usize);
+
+impl EncodedLen {
+    pub fn new() -> EncodedLen {
+        EncodedLen(0)
+    }
+
+    pub fn fixed32(self) -> EncodedLen {
+        EncodedLen(self.0 + 4)
+    }
+
+    pub fn fixed64(self) -> EncodedLen {
+        EncodedLen(self.0 + 8)
+    }
+
+    pub fn varint32(self, value: u32) -> EncodedLen {
+        EncodedLen(self.0 + varint32_length(value))
+    }
+
+    pub fn varint64(self, value: u64) -> EncodedLen {
+        EncodedLen(self.0 + varint64_length(value))
+    }
+
+    pub fn bytes(self, value: &[u8]) -> EncodedLen {
+        EncodedLen(self.0 + value.len())
+    }
+
+    pub fn length_prefixed_slice(self, value: &[u8]) -> EncodedLen {
+        // same varint32 prefix as `Encoder::length_prefixed_slice`
+        self.varint32(value.len() as u32).bytes(value)
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        self.0
+    }
+}
+
+/// Chains `put_*` writes into a buffer that is allocated once up front.
+///
+/// Size the encoder with the `EncodedLen` describing the same sequence of
+/// writes; `len()` then equals `EncodedLen::encoded_len()` once they are done.
+#[derive(Debug, Clone, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder { buf: Vec::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Encoder {
+        Encoder { buf: Vec::with_capacity(capacity) }
+    }
+
+    pub fn with_len(len: EncodedLen) -> Encoder {
+        Encoder::with_capacity(len.encoded_len())
+    }
+
+    /// Reuses `buf` as the destination, keeping its current contents.
+    pub fn from_vec(buf: Vec<u8>) -> Encoder {
+        Encoder { buf }
+    }
+
+    pub fn reserve(&mut self, len: EncodedLen) -> &mut Encoder {
+        self.buf.reserve(len.encoded_len());
+        self
+    }
+
+    pub fn fixed32(&mut self, value: u32) -> &mut Encoder {
+        put_fixed32(&mut self.buf, value);
+        self
+    }
+
+    pub fn fixed64(&mut self, value: u64) -> &mut Encoder {
+        put_fixed64(&mut self.buf, value);
+        self
+    }
+
+    pub fn varint32(&mut self, value: u32) -> &mut Encoder {
+        put_varint32(&mut self.buf, value);
+        self
+    }
+
+    pub fn varint64(&mut self, value: u64) -> &mut Encoder {
+        put_varint64(&mut self.buf, value);
+        self
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> &mut Encoder {
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    pub fn length_prefixed_slice(&mut self, value: &[u8]) -> result::Result<&mut Encoder, SliceTooLong> {
+        put_length_prefixed_slice(&mut self.buf, value)?;
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::random::Random;
+
+    #[test]
+    fn fixed32_bytes() {
+        let cases: [(u32, [u8; 4]); 6] = [
+            (0, [0x00, 0x00, 0x00, 0x00]),
+            (127, [0x7f, 0x00, 0x00, 0x00]),
+            (128, [0x80, 0x00, 0x00, 0x00]),
+            (16383, [0xff, 0x3f, 0x00, 0x00]),
+            (16384, [0x00, 0x40, 0x00, 0x00]),
+            (u32::MAX, [0xff, 0xff, 0xff, 0xff]),
+        ];
+        for &(value, ref bytes) in &cases {
+            let mut dst = Vec::new();
+            put_fixed32(&mut dst, value);
+            assert_eq!(&dst[..], &bytes[..], "{}", value);
+        }
+    }
+
+    #[test]
+    fn fixed64_bytes() {
+        let cases: [(u64, [u8; 8]); 8] = [
+            (0, [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            (127, [0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            (128, [0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            (16383, [0xff, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            (16384, [0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            (u64::from(u32::MAX), [0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00]),
+            (0x0102_0304_0506_0708, [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]),
+            (u64::MAX, [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+        ];
+        for &(value, ref bytes) in &cases {
+            let mut dst = Vec::new();
+            put_fixed64(&mut dst, value);
+            assert_eq!(&dst[..], &bytes[..], "{}", value);
+        }
+    }
+
+    #[test]
+    fn varint32_bytes() {
+        let cases: [(u32, &[u8]); 6] = [
+            (0, &[0x00]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (16383, &[0xff, 0x7f]),
+            (16384, &[0x80, 0x80, 0x01]),
+            (u32::MAX, &[0xff, 0xff, 0xff, 0xff, 0x0f]),
+        ];
+        for &(value, bytes) in &cases {
+            let mut dst = Vec::new();
+            put_varint32(&mut dst, value);
+            assert_eq!(&dst[..], bytes, "{}", value);
+            assert_eq!(varint32_length(value), bytes.len());
+        }
+    }
+
+    #[test]
+    fn varint64_bytes() {
+        let cases: [(u64, &[u8]); 7] = [
+            (0, &[0x00]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (16383, &[0xff, 0x7f]),
+            (16384, &[0x80, 0x80, 0x01]),
+            (u64::from(u32::MAX), &[0xff, 0xff, 0xff, 0xff, 0x0f]),
+            (u64::MAX, &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]),
+        ];
+        for &(value, bytes) in &cases {
+            let mut dst = Vec::new();
+            put_varint64(&mut dst, value);
+            assert_eq!(&dst[..], bytes, "{}", value);
+            assert_eq!(varint64_length(value), bytes.len());
+        }
+    }
+
+    #[test]
+    fn fixed32() {
+        let mut s = Vec::new();
+        for v in 0..100_000 {
+            put_fixed32(&mut s, v);
+        }
+
+        let mut p = &s[..];
+        for v in 0..100_000 {
+            assert_eq!(v, try_decode_fixed32(p).unwrap());
+            p = &p[4..];
+        }
+        assert!(p.is_empty());
+    }
+
+    #[test]
+    fn fixed64() {
+        let mut s = Vec::new();
+        for power in 0..64 {
+            let v = 1u64 << power;
+            put_fixed64(&mut s, v - 1);
+            put_fixed64(&mut s, v);
+            put_fixed64(&mut s, v + 1);
+        }
+
+        let mut p = &s[..];
+        for power in 0..64 {
+            let v = 1u64 << power;
+            for &expected in &[v - 1, v, v + 1] {
+                assert_eq!(expected, try_decode_fixed64(p).unwrap());
+                p = &p[8..];
+            }
+        }
+        assert!(p.is_empty());
+    }
+
+    // Test that encoding routines generate little-endian encodings
+    #[test]
+    fn encoding_output() {
+        let mut dst = Vec::new();
+        put_fixed32(&mut dst, 0x0403_0201);
+        assert_eq!(dst, [0x01, 0x02, 0x03, 0x04]);
+
+        dst.clear();
+        put_fixed64(&mut dst, 0x0807_0605_0403_0201);
+        assert_eq!(dst, [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+    }
+
+    #[test]
+    fn varint32() {
+        let mut s = Vec::new();
+        for i in 0..(32 * 32) {
+            let v = (i / 32) << (i % 32);
+            put_varint32(&mut s, v);
+        }
+
+        let mut p = &s[..];
+        for i in 0..(32 * 32) {
+            let expected = (i / 32) << (i % 32);
+            let start = p.len();
+            let (rest, actual) = get_varint32(p).unwrap();
+            assert_eq!(expected, actual);
+            assert_eq!(varint32_length(actual), start - rest.len());
+            p = rest;
+        }
+        assert!(p.is_empty());
+    }
+
+    #[test]
+    fn varint64() {
+        // Construct the list of values to check
+        let mut values = vec![0, 100, !0u64, !0u64 - 1];
+        for k in 0..64 {
+            // Test values near powers of two
+            let power = 1u64 << k;
+            values.push(power);
+            values.push(power - 1);
+            values.push(power + 1);
+        }
+
+        let mut s = Vec::new();
+        for &v in &values {
+            put_varint64(&mut s, v);
+        }
+
+        let mut p = &s[..];
+        for &expected in &values {
+            let start = p.len();
+            let (rest, actual) = get_varint64(p).unwrap();
+            assert_eq!(expected, actual);
+            assert_eq!(varint64_length(actual), start - rest.len());
+            p = rest;
+        }
+        assert!(p.is_empty());
+    }
+
+    #[test]
+    fn strings() {
+        let mut s = Vec::new();
+        put_length_prefixed_slice(&mut s, b"").unwrap();
+        put_length_prefixed_slice(&mut s, b"foo").unwrap();
+        put_length_prefixed_slice(&mut s, b"bar").unwrap();
+        put_length_prefixed_slice(&mut s, &[b'x'; 200]).unwrap();
+
+        let mut input = &s[..];
+        for expected in &[&b""[..], b"foo", b"bar", &[b'x'; 200]] {
+            let (rest, v) = get_length_prefixed_slice(input).unwrap();
+            assert_eq!(*expected, v);
+            input = rest;
+        }
+        assert!(input.is_empty());
+    }
+
+    // Random values with every bit width equally likely.
+    fn random_u64(rnd: &mut Random) -> u64 {
+        let v = (u64::from(rnd.next_u32()) << 33)
+            ^ (u64::from(rnd.next_u32()) << 2)
+            ^ u64::from(rnd.next_u32());
+        v >> rnd.uniform(64)
+    }
+
+    #[test]
+    fn varint64_random_round_trip() {
+        let mut rnd = Random::new(301);
+        let mut s = Vec::new();
+        let values: Vec<u64> = (0..100_000).map(|_| random_u64(&mut rnd)).collect();
+        for &v in &values {
+            let start = s.len();
+            put_varint64(&mut s, v);
+            assert_eq!(varint64_length(v), s.len() - start);
+        }
+
+        let mut p = &s[..];
+        for &expected in &values {
+            let (rest, actual) = get_varint64(p).unwrap();
+            assert_eq!(expected, actual);
+            p = rest;
+        }
+        assert!(p.is_empty());
+    }
+
+    #[test]
+    fn varint32_random_round_trip() {
+        let mut rnd = Random::new(302);
+        let mut s = Vec::new();
+        let values: Vec<u32> = (0..100_000).map(|_| (random_u64(&mut rnd) >> 32) as u32).collect();
+        for &v in &values {
+            put_varint32(&mut s, v);
+        }
+
+        let mut p = &s[..];
+        for &expected in &values {
+            let (rest, actual) = get_varint32(p).unwrap();
+            assert_eq!(expected, actual);
+            p = rest;
+        }
+        assert!(p.is_empty());
+
+        let mut batch = vec![0; values.len()];
+        assert!(decode_varint32_batch(&s, &mut batch).unwrap().is_empty());
+        assert_eq!(batch, values);
+    }
+
+    // Encodings that both get_varint32 and decode_varint32_batch must reject.
+    const BAD_VARINT32: &[&[u8]] = &[
+        // 5th byte with the high bits set
+        &[0x81, 0x82, 0x83, 0x84, 0x10],
+        &[0xff, 0xff, 0xff, 0xff, 0x1f],
+        &[0xff, 0xff, 0xff, 0xff, 0x7f],
+        // 6 bytes
+        &[0x80, 0x80, 0x80, 0x80, 0x80, 0x00],
+        &[0xff, 0xff, 0xff, 0xff, 0x8f, 0x00],
+    ];
+
+    #[test]
+    fn varint32_overflow() {
+        for &input in BAD_VARINT32 {
+            assert!(get_varint32(input).unwrap_err().is_corruption(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn varint32_truncation() {
+        let large_value = (1u32 << 31) + 100;
+        let mut s = Vec::new();
+        put_varint32(&mut s, large_value);
+        for len in 0..s.len() {
+            assert!(get_varint32(&s[..len]).is_err());
+        }
+        let (rest, result) = get_varint32(&s).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(large_value, result);
+    }
+
+    #[test]
+    fn varint64_overflow() {
+        let bad: &[&[u8]] = &[
+            // 10th byte greater than 1
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02],
+            &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f],
+            // 11 bytes
+            &[0x81, 0x82, 0x83, 0x84, 0x85, 0x81, 0x82, 0x83, 0x84, 0x85, 0x11],
+            &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00],
+        ];
+        for &input in bad {
+            assert!(get_varint64(input).unwrap_err().is_corruption(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn varint64_truncation() {
+        let large_value = (1u64 << 63) + 100;
+        let mut s = Vec::new();
+        put_varint64(&mut s, large_value);
+        for len in 0..s.len() {
+            assert!(get_varint64(&s[..len]).is_err());
+        }
+        let (rest, result) = get_varint64(&s).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(large_value, result);
+    }
+
+    #[test]
+    fn varint32_batch_rejects_bad_encodings() {
+        for &bad in BAD_VARINT32 {
+            // with and without enough trailing bytes for the word-at-a-time path
+            for padding in &[0, 8] {
+                let mut input = bad.to_vec();
+                input.resize(bad.len() + padding, 0);
+                let mut out = [0; 1];
+                assert!(decode_varint32_batch(&input, &mut out).unwrap_err().is_corruption());
+
+                let mut prefixed = vec![0x01; 8];
+                prefixed.extend_from_slice(&input);
+                let mut out = [0; 9];
+                assert!(decode_varint32_batch(&prefixed, &mut out).is_err());
+            }
+        }
+
+        let mut s = Vec::new();
+        put_varint32(&mut s, u32::MAX);
+        s.resize(16, 0);
+        for len in 0..5 {
+            assert!(decode_varint32_batch(&s[..len], &mut [0; 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn varint32_batch_matches_scalar() {
+        fn scalar<'a>(mut input: &'a [u8], out: &mut [u32]) -> Result<&'a [u8]> {
+            for v in out.iter_mut() {
+                let (rest, value) = get_varint32(input)?;
+                *v = value;
+                input = rest;
+            }
+            Ok(input)
+        }
+
+        let mut rnd = Random::new(303);
+        for _ in 0..10_000 {
+            let n = rnd.uniform(40) as usize;
+            let mut s = Vec::new();
+            for _ in 0..n {
+                // favour short encodings so the 8-values-at-once path runs
+                let v = (random_u64(&mut rnd) >> 32) as u32 >> (rnd.uniform(4) * 8);
+                put_varint32(&mut s, v);
+            }
+            // corrupt, truncate or extend some of the inputs
+            match rnd.uniform(4) {
+                0 if !s.is_empty() => {
+                    let i = rnd.uniform(s.len() as u32) as usize;
+                    s[i] = rnd.uniform(256) as u8;
+                }
+                1 => {
+                    let len = rnd.uniform(s.len() as u32 + 1) as usize;
+                    s.truncate(len);
+                }
+                2 => s.extend_from_slice(&[0xff; 6]),
+                _ => {}
+            }
+            let count = rnd.uniform(n as u32 + 2) as usize;
+            let (mut expected, mut actual) = (vec![0; count], vec![0; count]);
+            match (scalar(&s, &mut expected), decode_varint32_batch(&s, &mut actual)) {
+                (Ok(a), Ok(b)) => {
+                    assert_eq!(a, b);
+                    assert_eq!(expected, actual);
+                }
+                (Err(_), Err(_)) => {}
+                (a, b) => panic!("{:?} vs {:?} for {:?}", a, b, s),
+            }
+        }
+    }
+
+    #[test]
+    fn encoded_len_matches_encoder() {
+        let value = [b'v'; 300];
+        let len = EncodedLen::new()
+            .fixed32()
+            .fixed64()
+            .varint32(300)
+            .varint64(u64::MAX)
+            .bytes(b"abc")
+            .length_prefixed_slice(&value)
+            .length_prefixed_slice(b"");
+        let mut encoder = Encoder::with_len(len);
+        encoder
+            .fixed32(1)
+            .fixed64(2)
+            .varint32(300)
+            .varint64(u64::MAX)
+            .bytes(b"abc")
+            .length_prefixed_slice(&value)
+            .unwrap()
+            .length_prefixed_slice(b"")
+            .unwrap();
+        assert_eq!(len.encoded_len(), encoder.len());
+        assert_eq!(encoder.len(), 4 + 8 + 2 + 10 + 3 + 2 + 300 + 1);
+
+        let mut expected = Vec::new();
+        put_fixed32(&mut expected, 1);
+        put_fixed64(&mut expected, 2);
+        put_varint32(&mut expected, 300);
+        put_varint64(&mut expected, u64::MAX);
+        expected.extend_from_slice(b"abc");
+        put_length_prefixed_slice(&mut expected, &value).unwrap();
+        put_length_prefixed_slice(&mut expected, b"").unwrap();
+        assert_eq!(encoder.into_vec(), expected);
+    }
+
+    #[test]
+    fn fixed_big_endian() {
+        let mut s = Vec::new();
+        put_fixed32_be(&mut s, 0x0102_0304);
+        put_fixed64_be(&mut s, 0x0102_0304_0506_0708);
+        assert_eq!(s, [1, 2, 3, 4, 1, 2, 3, 4, 5, 6, 7, 8]);
+        let (rest, v32) = get_fixed32_be(&s).unwrap();
+        assert_eq!(v32, 0x0102_0304);
+        let (rest, v64) = get_fixed64_be(rest).unwrap();
+        assert_eq!(v64, 0x0102_0304_0506_0708);
+        assert!(rest.is_empty());
+        assert!(get_fixed32_be(&s[..3]).unwrap_err().is_corruption());
+        assert!(get_fixed64_be(&s[..7]).unwrap_err().is_corruption());
+    }
+
+    fn assert_bytewise_sorted(encoded: &[Vec<u8>]) {
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} >= {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn ordered_ints() {
+        let values32 = [i32::MIN, i32::MIN + 1, -256, -1, 0, 1, 256, i32::MAX - 1, i32::MAX];
+        let encoded: Vec<_> = values32
+            .iter()
+            .map(|&v| {
+                let mut s = Vec::new();
+                put_ordered_i32(&mut s, v);
+                assert_eq!(s.len(), 4);
+                let (rest, decoded) = get_ordered_i32(&s).unwrap();
+                assert!(rest.is_empty());
+                assert_eq!(decoded, v);
+                s
+            })
+            .collect();
+        assert_bytewise_sorted(&encoded);
+
+        let values64 = [i64::MIN, i64::MIN + 1, -(1 << 32), -1, 0, 1, 1 << 32, i64::MAX - 1, i64::MAX];
+        let encoded: Vec<_> = values64
+            .iter()
+            .map(|&v| {
+                let mut s = Vec::new();
+                put_ordered_i64(&mut s, v);
+                assert_eq!(s.len(), 8);
+                let (rest, decoded) = get_ordered_i64(&s).unwrap();
+                assert!(rest.is_empty());
+                assert_eq!(decoded, v);
+                s
+            })
+            .collect();
+        assert_bytewise_sorted(&encoded);
+
+        assert!(get_ordered_i32(&[0; 3]).unwrap_err().is_corruption());
+        assert!(get_ordered_i64(&[0; 7]).unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn ordered_floats() {
+        let values32 = [
+            f32::NEG_INFINITY,
+            f32::MIN,
+            -1.5,
+            -f32::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f32::MIN_POSITIVE,
+            1.5,
+            f32::MAX,
+            f32::INFINITY,
+            f32::NAN,
+        ];
+        let encoded: Vec<_> = values32
+            .iter()
+            .map(|&v| {
+                let mut s = Vec::new();
+                put_ordered_f32(&mut s, v);
+                let (rest, decoded) = get_ordered_f32(&s).unwrap();
+                assert!(rest.is_empty());
+                // compare bits so that -0.0 and NaN round trip exactly
+                assert_eq!(decoded.to_bits(), v.to_bits());
+                s
+            })
+            .collect();
+        assert_bytewise_sorted(&encoded);
+
+        let values64 = [
+            f64::NEG_INFINITY,
+            f64::MIN,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            1.5,
+            f64::MAX,
+            f64::INFINITY,
+            f64::NAN,
+        ];
+        let encoded: Vec<_> = values64
+            .iter()
+            .map(|&v| {
+                let mut s = Vec::new();
+                put_ordered_f64(&mut s, v);
+                let (rest, decoded) = get_ordered_f64(&s).unwrap();
+                assert!(rest.is_empty());
+                assert_eq!(decoded.to_bits(), v.to_bits());
+                s
+            })
+            .collect();
+        assert_bytewise_sorted(&encoded);
+
+        // a NaN with the sign bit set sorts before -inf
+        let mut neg_nan = Vec::new();
+        put_ordered_f64(&mut neg_nan, -f64::NAN);
+        assert!(neg_nan < encoded[0]);
+        assert_eq!(get_ordered_f64(&neg_nan).unwrap().1.to_bits(), (-f64::NAN).to_bits());
+    }
+
+    #[test]
+    fn ordered_bytes() {
+        let values: [&[u8]; 12] = [
+            b"",
+            b"\x00",
+            b"\x00\x00",
+            b"\x00\x01",
+            b"\x01",
+            b"a",
+            b"a\x00",
+            b"a\x00b",
+            b"ab",
+            b"b",
+            b"\xff",
+            b"\xff\xff",
+        ];
+        let encoded: Vec<_> = values
+            .iter()
+            .map(|&v| {
+                let mut s = Vec::new();
+                put_ordered_bytes(&mut s, v);
+                // a trailing field must not be mistaken for part of the value
+                s.extend_from_slice(b"\x00\x01tail");
+                let (rest, decoded) = get_ordered_bytes(&s).unwrap();
+                assert_eq!(rest, b"\x00\x01tail");
+                assert_eq!(decoded, v);
+                s.truncate(s.len() - 6);
+                s
+            })
+            .collect();
+        assert_bytewise_sorted(&encoded);
+
+        let mut s = Vec::new();
+        put_ordered_bytes(&mut s, b"a\x00b");
+        assert_eq!(s, b"a\x00\xffb\x00\x01");
+
+        // the sort order carries over to keys built from several fields
+        let mut a = Vec::new();
+        put_ordered_bytes(&mut a, b"a");
+        put_ordered_i32(&mut a, i32::MAX);
+        let mut b = Vec::new();
+        put_ordered_bytes(&mut b, b"a\x00");
+        put_ordered_i32(&mut b, i32::MIN);
+        assert!(a < b);
+    }
+
+    #[test]
+    fn ordered_bytes_errors() {
+        let err = get_ordered_bytes(b"ab\x00\x02").unwrap_err();
+        assert!(err.is_corruption());
+        assert!(err.message().contains("0x00 0x02"), "{}", err);
+
+        for input in [&b""[..], b"abc", b"ab\x00", b"ab\x00\xff"].iter() {
+            let err = get_ordered_bytes(input).unwrap_err();
+            assert!(err.is_corruption());
+            assert_eq!(err.message(), "unterminated ordered bytes");
+        }
+    }
+
+    #[test]
+    fn decoder_reads_fields_in_order() {
+        let mut s = Vec::new();
+        s.push(7);
+        put_fixed32(&mut s, 0xdead_beef);
+        put_fixed64(&mut s, u64::MAX - 1);
+        put_varint32(&mut s, 300);
+        put_varint64(&mut s, 1 << 40);
+        put_length_prefixed_slice(&mut s, b"hello").unwrap();
+
+        let mut d = Decoder::new(&s);
+        assert_eq!(d.position(), 0);
+        assert_eq!(d.remaining(), &s[..]);
+        assert_eq!(d.read_u8("tag").unwrap(), 7);
+        assert_eq!(d.position(), 1);
+        assert_eq!(d.read_fixed32("crc").unwrap(), 0xdead_beef);
+        assert_eq!(d.position(), 5);
+        assert_eq!(d.read_fixed64("sequence").unwrap(), u64::MAX - 1);
+        assert_eq!(d.position(), 13);
+        assert_eq!(d.read_varint32("count").unwrap(), 300);
+        assert_eq!(d.position(), 15);
+        assert_eq!(d.read_varint64("size").unwrap(), 1 << 40);
+        assert_eq!(d.position(), 21);
+        assert_eq!(d.remaining(), &s[21..]);
+        assert_eq!(d.read_length_prefixed_slice("key").unwrap(), b"hello");
+        assert_eq!(d.position(), s.len());
+        assert!(d.remaining().is_empty());
+        assert!(d.is_empty());
+    }
+
+    #[test]
+    fn decoder_truncated_fields() {
+        let s = [1, 2, 3];
+        let mut d = Decoder::new(&s);
+        assert_eq!(d.read_u8("tag").unwrap(), 1);
+
+        let err = d.read_fixed32("crc").unwrap_err();
+        assert_eq!(err, DecodeError { field: "crc", offset: 1, kind: DecodeErrorKind::Truncated });
+        let err = d.read_fixed64("sequence").unwrap_err();
+        assert_eq!(err, DecodeError { field: "sequence", offset: 1, kind: DecodeErrorKind::Truncated });
+        // a failed read does not consume anything
+        assert_eq!(d.position(), 1);
+        assert_eq!(d.remaining(), &[2, 3]);
+
+        d.read_fixed32("skip").unwrap_err();
+        assert_eq!(d.read_u8("a").unwrap(), 2);
+        assert_eq!(d.read_u8("b").unwrap(), 3);
+        let err = d.read_u8("c").unwrap_err();
+        assert_eq!(err, DecodeError { field: "c", offset: 3, kind: DecodeErrorKind::Truncated });
+        assert_eq!(err.to_string(), "truncated c at offset 3");
+    }
+
+    #[test]
+    fn decoder_varint_errors() {
+        let kind32 = |input: &[u8]| {
+            let mut s = vec![0];
+            s.extend_from_slice(input);
+            let mut d = Decoder::new(&s);
+            d.read_u8("pad").unwrap();
+            let err = d.read_varint32("value").unwrap_err();
+            assert_eq!((err.field, err.offset), ("value", 1));
+            assert_eq!(d.position(), 1);
+            err.kind
+        };
+        let kind64 = |input: &[u8]| {
+            let err = Decoder::new(input).read_varint64("value").unwrap_err();
+            assert_eq!((err.field, err.offset), ("value", 0));
+            err.kind
+        };
+
+        // running off the end of the input is truncation
+        assert_eq!(kind32(&[]), DecodeErrorKind::Truncated);
+        assert_eq!(kind32(&[0x80]), DecodeErrorKind::Truncated);
+        assert_eq!(kind32(&[0xff, 0xff, 0xff, 0xff]), DecodeErrorKind::Truncated);
+        assert_eq!(kind64(&[]), DecodeErrorKind::Truncated);
+        assert_eq!(kind64(&[0xff; 9]), DecodeErrorKind::Truncated);
+
+        // overflowing or overlong encodings are malformed
+        for &bad in BAD_VARINT32 {
+            assert_eq!(kind32(bad), DecodeErrorKind::Malformed, "{:?}", bad);
+        }
+        assert_eq!(kind32(&[0x80; 5]), DecodeErrorKind::Malformed);
+        assert_eq!(kind64(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]), DecodeErrorKind::Malformed);
+        assert_eq!(kind64(&[0x80; 10]), DecodeErrorKind::Malformed);
+        assert_eq!(kind64(&[0x80; 11]), DecodeErrorKind::Malformed);
+    }
+
+    #[test]
+    fn decoder_length_prefixed_slice_rolls_back() {
+        let mut s = vec![9];
+        put_varint32(&mut s, 200);
+        s.extend_from_slice(&[b'x'; 199]);
+
+        let mut d = Decoder::new(&s);
+        d.read_u8("tag").unwrap();
+        let err = d.read_length_prefixed_slice("value").unwrap_err();
+        assert_eq!(err, DecodeError { field: "value", offset: 1, kind: DecodeErrorKind::Truncated });
+        // the length prefix was not consumed
+        assert_eq!(d.position(), 1);
+        assert_eq!(d.remaining(), &s[1..]);
+        assert_eq!(d.read_varint32("len").unwrap(), 200);
+
+        // a bad length prefix is reported against the slice field
+        let mut d = Decoder::new(&[0x80]);
+        let err = d.read_length_prefixed_slice("value").unwrap_err();
+        assert_eq!(err, DecodeError { field: "value", offset: 0, kind: DecodeErrorKind::Truncated });
+        assert_eq!(d.position(), 0);
+
+        let err: Error = err.into();
+        assert!(err.is_corruption());
+        assert_eq!(err.to_string(), "Corruption: truncated value at offset 0");
+    }
+
+    #[test]
+    fn varint_signed32() {
+        // (value, encoded length, zigzag value)
+        let cases = [
+            (0, 1, 0u32),
+            (-1, 1, 1),
+            (1, 1, 2),
+            (-64, 1, 127),
+            (64, 2, 128),
+            (i32::MAX, 5, u32::MAX - 1),
+            (i32::MIN, 5, u32::MAX),
+        ];
+        for &(value, len, zigzag) in cases.iter() {
+            let mut s = Vec::new();
+            put_varint_signed32(&mut s, value);
+            assert_eq!(s.len(), len, "{}", value);
+            assert_eq!(varint_signed32_length(value), len);
+            let mut unsigned = Vec::new();
+            put_varint32(&mut unsigned, zigzag);
+            assert_eq!(s, unsigned);
+
+            let (rest, decoded) = get_varint_signed32(&s).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(decoded, value);
+            for n in 0..s.len() {
+                assert!(get_varint_signed32(&s[..n]).unwrap_err().is_corruption());
+            }
+        }
+        for &bad in BAD_VARINT32 {
+            assert!(get_varint_signed32(bad).unwrap_err().is_corruption(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn varint_signed64() {
+        // (value, encoded length, zigzag value)
+        let cases = [
+            (0, 1, 0u64),
+            (-1, 1, 1),
+            (1, 1, 2),
+            (-64, 1, 127),
+            (64, 2, 128),
+            (i64::MAX, 10, u64::MAX - 1),
+            (i64::MIN, 10, u64::MAX),
+        ];
+        for &(value, len, zigzag) in cases.iter() {
+            let mut s = Vec::new();
+            put_varint_signed64(&mut s, value);
+            assert_eq!(s.len(), len, "{}", value);
+            assert_eq!(varint_signed64_length(value), len);
+            let mut unsigned = Vec::new();
+            put_varint64(&mut unsigned, zigzag);
+            assert_eq!(s, unsigned);
+
+            let (rest, decoded) = get_varint_signed64(&s).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(decoded, value);
+            for n in 0..s.len() {
+                assert!(get_varint_signed64(&s[..n]).unwrap_err().is_corruption());
+            }
+        }
+        let overlong: &[&[u8]] = &[
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02],
+            &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00],
+        ];
+        for &bad in overlong {
+            assert!(get_varint_signed64(bad).unwrap_err().is_corruption(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn varint_signed_random_round_trip() {
+        let mut rnd = Random::new(301);
+        let mut s = Vec::new();
+        let mut values = Vec::new();
+        for _ in 0..1000 {
+            let value = random_u64(&mut rnd) as i64;
+            put_varint_signed64(&mut s, value);
+            put_varint_signed32(&mut s, value as i32);
+            values.push(value);
+        }
+        let mut input = &s[..];
+        for &value in &values {
+            let (rest, decoded) = get_varint_signed64(input).unwrap();
+            assert_eq!(decoded, value);
+            let (rest, decoded) = get_varint_signed32(rest).unwrap();
+            assert_eq!(decoded, value as i32);
+            input = rest;
+        }
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn length_prefixed_slice64() {
+        let big = vec![b'x'; 200];
+        let mut s = Vec::new();
+        put_length_prefixed_slice64(&mut s, b"");
+        put_length_prefixed_slice64(&mut s, b"foo");
+        put_length_prefixed_slice64(&mut s, &big);
+        assert_eq!(&s[..5], b"\x00\x03foo");
+        assert_eq!(&s[5..7], &[0xc8, 0x01]);
+
+        let (rest, v) = get_length_prefixed_slice64(&s).unwrap();
+        assert_eq!(v, b"");
+        let (rest, v) = get_length_prefixed_slice64(rest).unwrap();
+        assert_eq!(v, b"foo");
+        let (rest, v) = get_length_prefixed_slice64(rest).unwrap();
+        assert_eq!(v, &big[..]);
+        assert!(rest.is_empty());
+
+        // the 32- and 64-bit variants share an encoding for short values
+        let (_, v) = get_length_prefixed_slice(&s[1..]).unwrap();
+        assert_eq!(v, b"foo");
+    }
+
+    #[test]
+    fn length_prefixed_slice_truncated_payload() {
+        let mut s = Vec::new();
+        put_length_prefixed_slice64(&mut s, b"hello");
+        for n in 0..s.len() {
+            assert!(get_length_prefixed_slice(&s[..n]).unwrap_err().is_corruption());
+            assert!(get_length_prefixed_slice64(&s[..n]).unwrap_err().is_corruption());
+        }
+        let err = get_length_prefixed_slice64(&s[..4]).unwrap_err();
+        assert_eq!(err.message(), "truncated slice: length 5 but 3 bytes left");
+
+        // a length that does not fit in memory is reported, not truncated
+        let mut s = Vec::new();
+        put_varint64(&mut s, u64::MAX);
+        s.extend_from_slice(b"abc");
+        assert!(get_length_prefixed_slice64(&s).unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn length_prefixed_slice_bounded() {
+        let mut s = Vec::new();
+        put_length_prefixed_slice(&mut s, b"hello").unwrap();
+        s.extend_from_slice(b"rest");
+
+        let (rest, v) = get_length_prefixed_slice_bounded(&s, 5).unwrap();
+        assert_eq!(v, b"hello");
+        assert_eq!(rest, b"rest");
+        assert!(get_length_prefixed_slice_bounded(&s, usize::MAX).is_ok());
+
+        let err = get_length_prefixed_slice_bounded(&s, 4).unwrap_err();
+        assert!(err.is_corruption());
+        assert_eq!(err.message(), "slice length 5 exceeds the limit 4");
+
+        // the limit is checked before the payload
+        let mut s = Vec::new();
+        put_varint32(&mut s, 1000);
+        assert!(get_length_prefixed_slice_bounded(&s, 999).unwrap_err().message().contains("exceeds"));
+        assert!(get_length_prefixed_slice_bounded(&s, 1000).unwrap_err().message().contains("truncated"));
+    }
+
+    #[test]
+    fn slice_too_long_is_invalid_argument() {
+        let e = SliceTooLong { len: 5000, max: 4096 };
+        assert_eq!(e.to_string(), "slice of 5000 bytes exceeds the maximum length 4096");
+        let err: Error = e.into();
+        assert!(err.is_invalid_argument());
+        assert_eq!(err.message(), "slice of 5000 bytes exceeds the maximum length 4096");
+    }
 }
@@ -0,0 +1,150 @@
+use std::cmp::Ordering;
+
+use error::{Error, Result};
+
+/// A `Comparator` object provides a total order across byte slices that are
+/// used as keys in an sstable or a database. A `Comparator` implementation
+/// must be thread-safe since leveldb may invoke its methods concurrently from
+/// multiple threads.
+pub trait Comparator: Send + Sync {
+    /// Three-way comparison.
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    /// The name of the comparator. Used to check for comparator mismatches
+    /// (i.e., a DB created with one comparator is accessed using a different
+    /// comparator).
+    ///
+    /// The client of this package should switch to a new name whenever the
+    /// comparator implementation changes in a way that will cause the
+    /// relative ordering of any two keys to change.
+    ///
+    /// Names starting with "leveldb." are reserved and should not be used by
+    /// any clients of this package.
+    fn name(&self) -> &str;
+
+    // Advanced functions: these are used to reduce the space requirements
+    // for internal data structures like index blocks.
+
+    /// If `*start < limit`, changes `*start` to a short string in
+    /// `[start, limit)`. Simple comparator implementations may return with
+    /// `*start` unchanged, i.e., an implementation of this method that does
+    /// nothing is correct.
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &[u8]);
+
+    /// Changes `*key` to a short string `>= *key`. Simple comparator
+    /// implementations may return with `*key` unchanged, i.e., an
+    /// implementation of this method that does nothing is correct.
+    fn find_short_successor(&self, key: &mut Vec<u8>);
+}
+
+/// Orders keys lexicographically by their bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn name(&self) -> &str {
+        "leveldb.BytewiseComparator"
+    }
+
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &[u8]) {
+        // Find length of common prefix
+        let diff_index = start
+            .iter()
+            .zip(limit)
+            .take_while(|&(a, b)| a == b)
+            .count();
+
+        if diff_index >= start.len().min(limit.len()) {
+            // Do not shorten if one string is a prefix of the other
+            return;
+        }
+        let diff_byte = start[diff_index];
+        if diff_byte < 0xff && diff_byte + 1 < limit[diff_index] {
+            start[diff_index] += 1;
+            start.truncate(diff_index + 1);
+            debug_assert_eq!(self.compare(start, limit), Ordering::Less);
+        }
+    }
+
+    fn find_short_successor(&self, key: &mut Vec<u8>) {
+        // Find first character that can be incremented
+        if let Some(i) = key.iter().position(|&b| b != 0xff) {
+            key[i] += 1;
+            key.truncate(i + 1);
+        }
+        // key is a run of 0xffs.  Leave it alone.
+    }
+}
+
+/// Checks that `comparator` is the one whose name was persisted alongside
+/// existing data, failing with `InvalidArgument` otherwise.
+pub fn check_comparator_name(comparator: &dyn Comparator, persisted_name: &str) -> Result<()> {
+    if comparator.name() == persisted_name {
+        Ok(())
+    } else {
+        Err(Error::invalid_argument(format!(
+            "{} does not match existing comparator {}",
+            persisted_name,
+            comparator.name()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comparator_name_mismatch() {
+        let comparator = BytewiseComparator;
+        check_comparator_name(&comparator, "leveldb.BytewiseComparator").unwrap();
+        let err = check_comparator_name(&comparator, "test.Reverse").unwrap_err();
+        assert!(err.is_invalid_argument());
+        assert_eq!(
+            err.message(),
+            "test.Reverse does not match existing comparator leveldb.BytewiseComparator"
+        );
+    }
+
+    fn shortest_separator(start: &[u8], limit: &[u8]) -> Vec<u8> {
+        let mut start = start.to_vec();
+        BytewiseComparator.find_shortest_separator(&mut start, limit);
+        start
+    }
+
+    fn short_successor(key: &[u8]) -> Vec<u8> {
+        let mut key = key.to_vec();
+        BytewiseComparator.find_short_successor(&mut key);
+        key
+    }
+
+    #[test]
+    fn find_shortest_separator() {
+        // shortened to one past the first differing byte
+        assert_eq!(shortest_separator(b"foo", b"hello"), b"g");
+        assert_eq!(shortest_separator(b"abc1xyz", b"abc9"), b"abc2");
+        // diff_byte + 1 == limit byte: nothing shorter fits in between
+        assert_eq!(shortest_separator(b"abc1xyz", b"abc2"), b"abc1xyz");
+        // one is a prefix of the other
+        assert_eq!(shortest_separator(b"foo", b"foobar"), b"foo");
+        assert_eq!(shortest_separator(b"foobar", b"foo"), b"foobar");
+        assert_eq!(shortest_separator(b"foo", b"foo"), b"foo");
+        // a 0xff difference byte cannot be incremented
+        assert_eq!(shortest_separator(b"\xff", b"\x01"), b"\xff");
+        assert_eq!(shortest_separator(b"a\xff\x01", b"a\x01"), b"a\xff\x01");
+        assert_eq!(shortest_separator(b"\xff\x01", b"\xff\xff\x05"), b"\xff\x02");
+        assert_eq!(shortest_separator(b"a\xffz", b"a\xff\xff"), b"a\xff{");
+    }
+
+    #[test]
+    fn find_short_successor() {
+        assert_eq!(short_successor(b"foo"), b"g");
+        assert_eq!(short_successor(b"\xff\xffabc"), b"\xff\xffb");
+        assert_eq!(short_successor(b"\xff\xff"), b"\xff\xff");
+        assert_eq!(short_successor(b""), b"");
+    }
+}
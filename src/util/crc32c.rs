@@ -0,0 +1,198 @@
+//! CRC32C (Castagnoli) checksums, as used by the log and table formats.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+const POLY: u32 = 0x82f6_3b78;
+const MASK_DELTA: u32 = 0xa282_ead8;
+
+// TABLE[0] is the classic byte-at-a-time table; TABLE[k][b] is the crc of
+// byte `b` followed by `k` zero bytes, which lets the software path consume
+// four bytes per step.
+static TABLE: [[u32; 256]; 4] = make_table();
+
+const fn make_table() -> [[u32; 256]; 4] {
+    let mut table = [[0u32; 256]; 4];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            j += 1;
+        }
+        table[0][i] = crc;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 4 {
+        let mut i = 0;
+        while i < 256 {
+            let prev = table[k - 1][i];
+            table[k][i] = (prev >> 8) ^ table[0][(prev & 0xff) as usize];
+            i += 1;
+        }
+        k += 1;
+    }
+    table
+}
+
+/// Returns the crc32c of `concat(A, data)` where `init_crc` is the crc32c of
+/// some string A. `extend` is often used to maintain the crc32c of a stream
+/// of data.
+pub fn extend(init_crc: u32, data: &[u8]) -> u32 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("sse4.2") {
+            return unsafe { !extend_sse42(!init_crc, data) };
+        }
+    }
+    !extend_portable(!init_crc, data)
+}
+
+/// Returns the crc32c of `data`.
+pub fn value(data: &[u8]) -> u32 {
+    extend(0, data)
+}
+
+/// Returns a masked representation of `crc`.
+///
+/// Motivation: it is problematic to compute the CRC of a string that
+/// contains embedded CRCs. Therefore we recommend that CRCs stored somewhere
+/// (e.g., in files) should be masked before being stored.
+pub fn mask(crc: u32) -> u32 {
+    // rotate right by 15 bits and add a constant
+    crc.rotate_right(15).wrapping_add(MASK_DELTA)
+}
+
+/// Returns the crc whose masked representation is `masked_crc`.
+pub fn unmask(masked_crc: u32) -> u32 {
+    masked_crc.wrapping_sub(MASK_DELTA).rotate_left(15)
+}
+
+fn extend_portable(mut crc: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        crc ^= LittleEndian::read_u32(chunk);
+        crc = TABLE[3][(crc & 0xff) as usize]
+            ^ TABLE[2][((crc >> 8) & 0xff) as usize]
+            ^ TABLE[1][((crc >> 16) & 0xff) as usize]
+            ^ TABLE[0][(crc >> 24) as usize];
+    }
+    for &b in chunks.remainder() {
+        crc = (crc >> 8) ^ TABLE[0][((crc ^ u32::from(b)) & 0xff) as usize];
+    }
+    crc
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn extend_sse42(crc: u32, data: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut crc = u64::from(crc);
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        crc = _mm_crc32_u64(crc, LittleEndian::read_u64(chunk));
+    }
+    let mut crc = crc as u32;
+    for &b in chunks.remainder() {
+        crc = _mm_crc32_u8(crc, b);
+    }
+    crc
+}
+
+#[cfg(target_arch = "x86")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn extend_sse42(mut crc: u32, data: &[u8]) -> u32 {
+    use std::arch::x86::{_mm_crc32_u32, _mm_crc32_u8};
+
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        crc = _mm_crc32_u32(crc, LittleEndian::read_u32(chunk));
+    }
+    for &b in chunks.remainder() {
+        crc = _mm_crc32_u8(crc, b);
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn portable_value(data: &[u8]) -> u32 {
+        !extend_portable(!0, data)
+    }
+
+    // From rfc3720 section B.4.
+    fn standard_results(value: fn(&[u8]) -> u32) {
+        let mut buf = [0u8; 32];
+        assert_eq!(0x8a91_36aa, value(&buf));
+
+        buf = [0xff; 32];
+        assert_eq!(0x62a8_ab43, value(&buf));
+
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        assert_eq!(0x46dd_794e, value(&buf));
+
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = 31 - i as u8;
+        }
+        assert_eq!(0x113f_db5c, value(&buf));
+
+        let data: [u8; 48] = [
+            0x01, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00,
+            0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x18, 0x28, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(0xd996_3a56, value(&data));
+    }
+
+    #[test]
+    fn standard_results_extend() {
+        standard_results(value);
+    }
+
+    #[test]
+    fn standard_results_portable() {
+        standard_results(portable_value);
+    }
+
+    #[test]
+    fn portable_matches_extend() {
+        // Cover every remainder length of both the 4- and 8-byte loops.
+        let data: Vec<u8> = (0..200u32).map(|i| (i * 7 + 3) as u8).collect();
+        for start in 0..9 {
+            for end in start..data.len() {
+                let chunk = &data[start..end];
+                assert_eq!(value(chunk), portable_value(chunk));
+            }
+        }
+    }
+
+    #[test]
+    fn values() {
+        assert_ne!(value(b"a"), value(b"foo"));
+    }
+
+    #[test]
+    fn extends() {
+        assert_eq!(value(b"hello world"), extend(value(b"hello "), b"world"));
+        assert_eq!(
+            portable_value(b"hello world"),
+            !extend_portable(!portable_value(b"hello "), b"world")
+        );
+    }
+
+    #[test]
+    fn masking() {
+        let crc = value(b"foo");
+        assert_ne!(crc, mask(crc));
+        assert_ne!(crc, mask(mask(crc)));
+        assert_eq!(crc, unmask(mask(crc)));
+        assert_eq!(crc, unmask(unmask(mask(mask(crc)))));
+    }
+}
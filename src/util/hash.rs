@@ -0,0 +1,59 @@
+//! The murmur-like hash LevelDB uses for bloom filters and cache sharding.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Hashes `data` exactly like LevelDB's `Hash(data, n, seed)`.
+pub fn hash(data: &[u8], seed: u32) -> u32 {
+    // Similar to murmur hash
+    const M: u32 = 0xc6a4_a793;
+    const R: u32 = 24;
+    let mut h = seed ^ (data.len() as u32).wrapping_mul(M);
+
+    // Pick up four bytes at a time
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let w = LittleEndian::read_u32(chunk);
+        h = h.wrapping_add(w).wrapping_mul(M);
+        h ^= h >> 16;
+    }
+
+    // Pick up remaining bytes
+    let rest = chunks.remainder();
+    if rest.len() == 3 {
+        h = h.wrapping_add(u32::from(rest[2]) << 16);
+    }
+    if rest.len() >= 2 {
+        h = h.wrapping_add(u32::from(rest[1]) << 8);
+    }
+    if !rest.is_empty() {
+        h = h.wrapping_add(u32::from(rest[0])).wrapping_mul(M);
+        h ^= h >> R;
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_unsigned_issue() {
+        let data1 = [0x62];
+        let data2 = [0xc3, 0x97];
+        let data3 = [0xe2, 0x99, 0xa5];
+        let data4 = [0xe1, 0x80, 0xb9, 0x32];
+        let data5: [u8; 48] = [
+            0x01, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00,
+            0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x18, 0x28, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        assert_eq!(hash(&[], 0xbc9f_1d34), 0xbc9f_1d34);
+        assert_eq!(hash(&data1, 0xbc9f_1d34), 0xef13_45c4);
+        assert_eq!(hash(&data2, 0xbc9f_1d34), 0x5b66_3814);
+        assert_eq!(hash(&data3, 0xbc9f_1d34), 0x323c_078f);
+        assert_eq!(hash(&data4, 0xbc9f_1d34), 0xed21_633a);
+        assert_eq!(hash(&data5, 0x1234_5678), 0xf333_dabb);
+    }
+}
@@ -1 +1,7 @@
 pub mod coding;
+pub mod slice;
+pub mod crc32c;
+pub mod hash;
+pub mod arena;
+pub mod random;
+pub mod comparator;
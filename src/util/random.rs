@@ -0,0 +1,62 @@
+/// A very simple random number generator. Not especially good at generating
+/// truly random bits, but good enough for our needs in this package.
+#[derive(Debug, Clone)]
+pub struct Random {
+    seed: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Random {
+        let mut seed = seed & 0x7fff_ffff;
+        // Avoid bad seeds.
+        if seed == 0 || seed == 2_147_483_647 {
+            seed = 1;
+        }
+        Random { seed }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        const M: u64 = 2_147_483_647; // 2^31-1
+        const A: u64 = 16807; // bits 14, 8, 7, 5, 2, 1, 0
+        // We are computing
+        //       seed = (seed * A) % M,    where M = 2^31-1
+        //
+        // seed must not be zero or M, or else all subsequent computed values
+        // will be zero or M respectively.  For all other values, seed will end
+        // up cycling through every number in [1,M-1]
+        let product = u64::from(self.seed) * A;
+
+        // Compute (product % M) using the fact that ((x << 31) % M) == x.
+        let mut seed = (product >> 31) + (product & M);
+        // The first reduction may overflow by 1 bit, so we may need to
+        // repeat.  mod == M is not possible; using > allows the faster
+        // sign-bit-based test.
+        if seed > M {
+            seed -= M;
+        }
+        self.seed = seed as u32;
+        self.seed
+    }
+
+    /// Returns a uniformly distributed value in the range [0..n-1].
+    ///
+    /// REQUIRES: n > 0
+    pub fn uniform(&mut self, n: u32) -> u32 {
+        self.next_u32() % n
+    }
+
+    /// Randomly returns true ~"1/n" of the time, and false otherwise.
+    ///
+    /// REQUIRES: n > 0
+    pub fn one_in(&mut self, n: u32) -> bool {
+        self.next_u32().is_multiple_of(n)
+    }
+
+    /// Skewed: pick "base" uniformly from range [0,max_log] and then
+    /// return "base" random bits. The effect is to pick a number in the
+    /// range [0,2^max_log-1] with exponential bias towards smaller numbers.
+    pub fn skewed(&mut self, max_log: u32) -> u32 {
+        let base = self.uniform(max_log + 1);
+        self.uniform(1 << base)
+    }
+}
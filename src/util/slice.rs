@@ -0,0 +1,226 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Deref;
+
+use error::Result;
+use util::coding::get_length_prefixed_slice;
+
+/// A borrowed view of a byte string, the Rust counterpart of LevelDB's `Slice`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Slice<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Slice<'a> {
+    pub fn new(data: &'a [u8]) -> Slice<'a> {
+        Slice { data }
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.data = &[];
+    }
+
+    pub fn starts_with(&self, prefix: &[u8]) -> bool {
+        self.data.starts_with(prefix)
+    }
+
+    /// Drops the first `n` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the length of the slice.
+    pub fn remove_prefix(&mut self, n: usize) {
+        assert!(n <= self.len(), "remove_prefix({}) on a slice of {} bytes", n, self.len());
+        self.data = &self.data[n..];
+    }
+
+    /// Three-way bytewise comparison.
+    pub fn compare(&self, other: &Slice) -> Ordering {
+        self.data.cmp(other.data)
+    }
+
+    /// Returns the length of the common prefix of `self` and `other`.
+    pub fn difference_offset(&self, other: &Slice) -> usize {
+        self.data
+            .iter()
+            .zip(other.data)
+            .take_while(|&(a, b)| a == b)
+            .count()
+    }
+
+    /// Decodes a length-prefixed slice from the front of `self` and advances
+    /// past it. The result borrows from the same buffer as `self`.
+    pub fn get_length_prefixed_slice(&mut self) -> Result<Slice<'a>> {
+        get_length_prefixed_slice(self.data).map(|(remain, value)| {
+            self.data = remain;
+            Slice::new(value)
+        })
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.data.to_vec()
+    }
+}
+
+impl<'a> Deref for Slice<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'a> AsRef<[u8]> for Slice<'a> {
+    fn as_ref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'a> From<&'a [u8]> for Slice<'a> {
+    fn from(data: &'a [u8]) -> Slice<'a> {
+        Slice::new(data)
+    }
+}
+
+impl<'a> From<&'a Vec<u8>> for Slice<'a> {
+    fn from(data: &'a Vec<u8>) -> Slice<'a> {
+        Slice::new(data)
+    }
+}
+
+impl<'a> From<&'a str> for Slice<'a> {
+    fn from(data: &'a str) -> Slice<'a> {
+        Slice::new(data.as_bytes())
+    }
+}
+
+/// Prints printable ASCII as is and every other byte as `\xNN`.
+impl<'a> fmt::Display for Slice<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &b in self.data {
+            if (b' '..=b'~').contains(&b) {
+                write!(f, "{}", b as char)?;
+            } else {
+                write!(f, "\\x{:02x}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for Slice<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Slice(\"{}\")", self)
+    }
+}
+
+impl<'a> fmt::LowerHex for Slice<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.data {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::UpperHex for Slice<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.data {
+            write!(f, "{:02X}", b)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::coding::put_length_prefixed_slice;
+
+    #[test]
+    fn remove_prefix() {
+        let mut s = Slice::from("hello");
+        s.remove_prefix(0);
+        assert_eq!(s.data(), b"hello");
+        s.remove_prefix(2);
+        assert_eq!(s.data(), b"llo");
+        assert!(s.starts_with(b"ll"));
+        s.remove_prefix(3);
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "remove_prefix(4) on a slice of 3 bytes")]
+    fn remove_prefix_past_end() {
+        Slice::from("abc").remove_prefix(4);
+    }
+
+    #[test]
+    fn compare() {
+        let a = Slice::from("abc");
+        assert_eq!(a.compare(&Slice::from("abc")), Ordering::Equal);
+        assert_eq!(a.compare(&Slice::from("abd")), Ordering::Less);
+        assert_eq!(a.compare(&Slice::from("abb")), Ordering::Greater);
+        // a prefix sorts first
+        assert_eq!(a.compare(&Slice::from("abcd")), Ordering::Less);
+        assert_eq!(a.compare(&Slice::from("ab")), Ordering::Greater);
+        assert_eq!(a.compare(&Slice::default()), Ordering::Greater);
+        // bytes compare unsigned
+        assert_eq!(Slice::new(b"\xff").compare(&Slice::new(b"\x01")), Ordering::Greater);
+    }
+
+    #[test]
+    fn difference_offset() {
+        let a = Slice::from("hello");
+        assert_eq!(a.difference_offset(&Slice::from("help")), 3);
+        assert_eq!(a.difference_offset(&Slice::from("world")), 0);
+        assert_eq!(a.difference_offset(&Slice::from("hello")), 5);
+        // one is a prefix of the other
+        assert_eq!(a.difference_offset(&Slice::from("hell")), 4);
+        assert_eq!(Slice::from("hell").difference_offset(&a), 4);
+        assert_eq!(a.difference_offset(&Slice::default()), 0);
+    }
+
+    #[test]
+    fn formatting() {
+        let s = Slice::new(b"a b~\x00\x1f\x7f\xff\"");
+        assert_eq!(s.to_string(), "a b~\\x00\\x1f\\x7f\\xff\"");
+        assert_eq!(format!("{:?}", Slice::new(b"k\n")), "Slice(\"k\\x0a\")");
+        assert_eq!(format!("{:x}", Slice::new(b"\x00\xabz")), "00ab7a");
+        assert_eq!(format!("{:X}", Slice::new(b"\x00\xabz")), "00AB7A");
+        assert_eq!(format!("{:x}", Slice::default()), "");
+    }
+
+    #[test]
+    fn get_length_prefixed_slice() {
+        let mut buf = Vec::new();
+        put_length_prefixed_slice(&mut buf, b"foo").unwrap();
+        put_length_prefixed_slice(&mut buf, b"").unwrap();
+        buf.extend_from_slice(b"rest");
+
+        let mut input = Slice::new(&buf);
+        let foo = input.get_length_prefixed_slice().unwrap();
+        assert_eq!(foo.data(), b"foo");
+        assert_eq!(foo.data().as_ptr(), buf[1..].as_ptr());
+        assert_eq!(input.len(), buf.len() - 4);
+        assert!(input.get_length_prefixed_slice().unwrap().is_empty());
+        assert_eq!(input.data(), b"rest");
+        assert_eq!(input.data().as_ptr(), buf[5..].as_ptr());
+
+        // a failed decode leaves the input untouched
+        assert!(input.get_length_prefixed_slice().unwrap_err().is_corruption());
+        assert_eq!(input.data(), b"rest");
+    }
+}
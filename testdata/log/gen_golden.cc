@@ -0,0 +1,165 @@
+// Generates the golden log files in this directory.
+//
+// This is LevelDB's log::Writer (db/log_writer.cc) and portable crc32c
+// (util/crc32c.cc, EncodeFixed32 from util/coding.h) with the Env and
+// Slice plumbing replaced by std::string, so that it builds standalone:
+//
+//   g++ -O2 -o gen_golden gen_golden.cc && ./gen_golden .
+
+#include <cstdint>
+#include <cstdio>
+#include <string>
+
+namespace {
+
+const int kBlockSize = 32768;
+const int kHeaderSize = 4 + 2 + 1;
+
+enum RecordType { kZeroType = 0, kFullType = 1, kFirstType = 2, kMiddleType = 3, kLastType = 4 };
+const int kMaxRecordType = kLastType;
+
+uint32_t table[256];
+
+void InitTable() {
+  for (uint32_t i = 0; i < 256; i++) {
+    uint32_t crc = i;
+    for (int j = 0; j < 8; j++) crc = (crc & 1) ? (crc >> 1) ^ 0x82f63b78 : crc >> 1;
+    table[i] = crc;
+  }
+}
+
+uint32_t Extend(uint32_t init_crc, const char* data, size_t n) {
+  uint32_t l = init_crc ^ 0xffffffffu;
+  for (size_t i = 0; i < n; i++) l = table[(l ^ static_cast<uint8_t>(data[i])) & 0xff] ^ (l >> 8);
+  return l ^ 0xffffffffu;
+}
+
+uint32_t Value(const char* data, size_t n) { return Extend(0, data, n); }
+
+const uint32_t kMaskDelta = 0xa282ead8ul;
+
+uint32_t Mask(uint32_t crc) { return ((crc >> 15) | (crc << 17)) + kMaskDelta; }
+
+void EncodeFixed32(char* dst, uint32_t value) {
+  uint8_t* const buffer = reinterpret_cast<uint8_t*>(dst);
+  buffer[0] = static_cast<uint8_t>(value);
+  buffer[1] = static_cast<uint8_t>(value >> 8);
+  buffer[2] = static_cast<uint8_t>(value >> 16);
+  buffer[3] = static_cast<uint8_t>(value >> 24);
+}
+
+class Writer {
+ public:
+  explicit Writer(std::string* dest) : dest_(dest), block_offset_(0) {
+    for (int i = 0; i <= kMaxRecordType; i++) {
+      char t = static_cast<char>(i);
+      type_crc_[i] = Value(&t, 1);
+    }
+  }
+
+  void AddRecord(const std::string& slice) {
+    const char* ptr = slice.data();
+    size_t left = slice.size();
+    bool begin = true;
+    do {
+      const int leftover = kBlockSize - block_offset_;
+      if (leftover < kHeaderSize) {
+        if (leftover > 0) dest_->append(std::string("\x00\x00\x00\x00\x00\x00", leftover));
+        block_offset_ = 0;
+      }
+      const size_t avail = kBlockSize - block_offset_ - kHeaderSize;
+      const size_t fragment_length = (left < avail) ? left : avail;
+      RecordType type;
+      const bool end = (left == fragment_length);
+      if (begin && end) {
+        type = kFullType;
+      } else if (begin) {
+        type = kFirstType;
+      } else if (end) {
+        type = kLastType;
+      } else {
+        type = kMiddleType;
+      }
+      EmitPhysicalRecord(type, ptr, fragment_length);
+      ptr += fragment_length;
+      left -= fragment_length;
+      begin = false;
+    } while (left > 0);
+  }
+
+ private:
+  void EmitPhysicalRecord(RecordType t, const char* ptr, size_t length) {
+    char buf[kHeaderSize];
+    buf[4] = static_cast<char>(length & 0xff);
+    buf[5] = static_cast<char>(length >> 8);
+    buf[6] = static_cast<char>(t);
+    uint32_t crc = Extend(type_crc_[t], ptr, length);
+    crc = Mask(crc);
+    EncodeFixed32(buf, crc);
+    dest_->append(buf, kHeaderSize);
+    dest_->append(ptr, length);
+    block_offset_ += kHeaderSize + length;
+  }
+
+  std::string* dest_;
+  int block_offset_;
+  uint32_t type_crc_[kMaxRecordType + 1];
+};
+
+// Deterministic payload shared with the Rust tests.
+std::string Payload(size_t n) {
+  std::string s(n, '\0');
+  for (size_t i = 0; i < n; i++) s[i] = static_cast<char>(i % 251);
+  return s;
+}
+
+void Save(const std::string& dir, const char* name, const std::string& data) {
+  std::string path = dir + "/" + name;
+  FILE* f = std::fopen(path.c_str(), "wb");
+  std::fwrite(data.data(), 1, data.size(), f);
+  std::fclose(f);
+}
+
+}  // namespace
+
+int main(int argc, char** argv) {
+  InitTable();
+  std::string dir = argc > 1 ? argv[1] : ".";
+  std::string dest;
+
+  // A single zero-length FULL record.
+  dest.clear();
+  Writer(&dest).AddRecord("");
+  Save(dir, "empty.log", dest);
+
+  // Small FULL records.
+  dest.clear();
+  {
+    Writer w(&dest);
+    w.AddRecord("foo");
+    w.AddRecord("bar");
+    w.AddRecord(Payload(1000));
+  }
+  Save(dir, "full.log", dest);
+
+  // FIRST, MIDDLE, MIDDLE, LAST across three 32 KiB boundaries.
+  dest.clear();
+  {
+    Writer w(&dest);
+    w.AddRecord("small");
+    w.AddRecord(Payload(100000));
+    w.AddRecord("tail");
+  }
+  Save(dir, "fragmented.log", dest);
+
+  // Leaves 3 bytes at the end of the first block, which become a trailer.
+  dest.clear();
+  {
+    Writer w(&dest);
+    w.AddRecord(Payload(kBlockSize - 2 * kHeaderSize - 3));
+    w.AddRecord("");
+    w.AddRecord("after trailer");
+  }
+  Save(dir, "trailer.log", dest);
+  return 0;
+}